@@ -0,0 +1,372 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use reqwest::{
+    header::{HeaderMap, CONTENT_TYPE},
+    StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{rule::Rule, LinkKind};
+
+/// The smallest acceptable body of an HTML page, unless a rule says otherwise. Anything smaller is
+/// hardly a page at all.
+const MIN_HTML_SIZE: usize = 200;
+
+/// The smallest acceptable body of anything else: it should not be empty.
+const MIN_SIZE: usize = 1;
+
+/// The number of matches of a forbidden pattern to report on a single page, as a leftover in a
+/// template tends to show up everywhere.
+const MAX_MATCHES: usize = 5;
+
+/// How bad a finding is: a warning does not fail the check, but should be fixed anyway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// Something a check found wrong with a response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Finding {
+    /// The name of the check that found it, filled in by the crawler.
+    pub check: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    pub fn warning(message: impl Into<String>) -> Finding {
+        Finding {
+            check: String::new(),
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Finding {
+        Finding {
+            check: String::new(),
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+}
+
+/// What a check gets to see of a response, after any redirects.
+#[derive(Debug)]
+pub struct Response<'a> {
+    pub url: &'a Url,
+    pub kind: LinkKind,
+    pub status: StatusCode,
+    pub headers: &'a HeaderMap,
+    /// The body of an HTML page, or of another response when a rule or a check needs it, see
+    /// [`Check::needs_body`]. It is never downloaded for external links.
+    pub body: Option<&'a str>,
+    /// The size of the body in bytes, as received. External links only have the `Content-Length`.
+    pub size: Option<usize>,
+    /// The title of an HTML page.
+    pub title: Option<&'a str>,
+    /// The time it took to get the full response, including retries.
+    pub duration: Duration,
+    /// The config rules that match the URL and content type, in order.
+    pub rules: &'a [&'a Rule],
+}
+
+impl Response<'_> {
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    /// Whether a rule accepts the status of this response, even if it is not successful.
+    pub fn status_ignored(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.ignore_status.contains(&self.status.as_u16()))
+    }
+}
+
+/// A check that runs on every response that comes in, internal and external.
+///
+/// ```
+/// use tgcheck::check::{Check, Finding, Response};
+///
+/// struct NoLoremIpsum;
+///
+/// impl Check for NoLoremIpsum {
+///     fn name(&self) -> &str {
+///         "lorem-ipsum"
+///     }
+///
+///     // Plain text files too, not only HTML pages
+///     fn needs_body(&self, response: &Response) -> bool {
+///         response.content_type().is_some_and(|value| value.starts_with("text/"))
+///     }
+///
+///     fn check(&self, response: &Response) -> Vec<Finding> {
+///         match response.body {
+///             Some(body) if body.contains("Lorem ipsum") => vec![Finding::error("placeholder text")],
+///             _ => Vec::new(),
+///         }
+///     }
+/// }
+/// ```
+pub trait Check: Send + Sync {
+    /// A short name, to tell which check found what.
+    fn name(&self) -> &str;
+
+    /// Whether the check needs the body of a response that is not an HTML page, like a JSON
+    /// document or an SVG image. It gets the response before the body is read, so without a body
+    /// and size. HTML pages are always read.
+    fn needs_body(&self, _response: &Response) -> bool {
+        false
+    }
+
+    fn check(&self, response: &Response) -> Vec<Finding>;
+}
+
+impl fmt::Debug for dyn Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Fails responses that are not successful, unless a rule ignores their status.
+#[derive(Debug, Default)]
+pub struct Status;
+
+impl Status {
+    pub const NAME: &str = "status";
+}
+
+impl Check for Status {
+    fn name(&self) -> &str {
+        Status::NAME
+    }
+
+    fn check(&self, response: &Response) -> Vec<Finding> {
+        match response.status.is_success() || response.status_ignored() {
+            true => Vec::new(),
+            false => vec![Finding::error(response.status.to_string())],
+        }
+    }
+}
+
+/// Fails pages and assets whose body is smaller than the `min-size` or larger than the `max-size` of
+/// the rules that match them. Without a `min-size`, HTML pages should be at least 200 bytes, and
+/// anything else should not be empty.
+#[derive(Debug, Default)]
+pub struct Size;
+
+impl Size {
+    pub const NAME: &str = "size";
+}
+
+impl Check for Size {
+    fn name(&self) -> &str {
+        Size::NAME
+    }
+
+    fn check(&self, response: &Response) -> Vec<Finding> {
+        let Some(size) = response.size else {
+            return Vec::new();
+        };
+        if response.kind == LinkKind::External || response.status_ignored() {
+            return Vec::new();
+        }
+
+        let is_html = response
+            .content_type()
+            .is_none_or(|content_type| content_type.contains("html"));
+        let min_size = response
+            .rules
+            .iter()
+            .rev()
+            .find_map(|rule| rule.min_size)
+            .unwrap_or(if is_html { MIN_HTML_SIZE } else { MIN_SIZE });
+        let max_size = response.rules.iter().rev().find_map(|rule| rule.max_size);
+
+        match max_size {
+            _ if size < min_size => vec![Finding::error(format!(
+                "response too small: {size} bytes, expected at least {min_size}"
+            ))],
+            Some(max_size) if size > max_size => vec![Finding::error(format!(
+                "response too large: {size} bytes, expected at most {max_size}"
+            ))],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Fails pages and assets whose body does not match every `must-contain` pattern, or matches a
+/// `must-not-contain` pattern, of the rules that match them. Matches are reported by line.
+#[derive(Debug, Default)]
+pub struct Content;
+
+impl Content {
+    pub const NAME: &str = "content";
+}
+
+impl Check for Content {
+    fn name(&self) -> &str {
+        Content::NAME
+    }
+
+    fn check(&self, response: &Response) -> Vec<Finding> {
+        let Some(body) = response.body else {
+            return Vec::new();
+        };
+        if !response.status.is_success() {
+            return Vec::new();
+        }
+
+        let mut findings = Vec::new();
+        for rule in response.rules {
+            for pattern in &rule.must_contain {
+                if !pattern.is_match(body) {
+                    findings.push(Finding::error(format!("does not contain {pattern}")));
+                }
+            }
+
+            for pattern in &rule.must_not_contain {
+                let matches: Vec<_> = pattern.find_iter(body).collect();
+                for found in matches.iter().take(MAX_MATCHES) {
+                    let line = body[..found.start()].matches('\n').count() + 1;
+                    let text = found.as_str().lines().next().unwrap_or_default();
+                    findings.push(Finding::error(format!(
+                        "contains \"{text}\" on line {line}, matching {pattern}"
+                    )));
+                }
+                if matches.len() > MAX_MATCHES {
+                    findings.push(Finding::error(format!(
+                        "{} more matches of {pattern}",
+                        matches.len() - MAX_MATCHES
+                    )));
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// The checks that run unless others are configured.
+pub fn builtin() -> Vec<Arc<dyn Check>> {
+    vec![Arc::new(Status), Arc::new(Size), Arc::new(Content)]
+}
+
+/// Run every check on a response, and tell which check found what.
+pub(crate) fn run(checks: &[Arc<dyn Check>], response: &Response) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for check in checks {
+        for mut finding in check.check(response) {
+            finding.check = check.name().to_owned();
+            findings.push(finding);
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::*;
+
+    fn content(rule: &Rule, status: StatusCode, body: &str) -> Vec<String> {
+        let url = Url::parse("https://example.com/").unwrap();
+        let response = Response {
+            url: &url,
+            kind: LinkKind::Page,
+            status,
+            headers: &HeaderMap::new(),
+            body: Some(body),
+            size: Some(body.len()),
+            title: None,
+            duration: Duration::ZERO,
+            rules: &[rule],
+        };
+        let findings = Content.check(&response);
+        assert!(findings.iter().all(|f| f.severity == Severity::Error));
+        findings
+            .into_iter()
+            .map(|finding| finding.message)
+            .collect()
+    }
+
+    fn rule(must_contain: &[&str], must_not_contain: &[&str]) -> Rule {
+        let regexes = |patterns: &[&str]| patterns.iter().map(|p| Regex::new(p).unwrap()).collect();
+        Rule {
+            must_contain: regexes(must_contain),
+            must_not_contain: regexes(must_not_contain),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn must_contain() {
+        let rule = rule(&[r#"class="price""#, "(?i)add to cart"], &[]);
+        assert!(content(
+            &rule,
+            StatusCode::OK,
+            r#"<b class="price">1</b> Add to cart"#
+        )
+        .is_empty());
+        assert_eq!(
+            content(&rule, StatusCode::OK, "<b>1</b> add to cart"),
+            [r#"does not contain class="price""#]
+        );
+    }
+
+    #[test]
+    fn must_not_contain_lines() {
+        let rule = rule(&[], &["TODO", r"\{\{.*?\}\}"]);
+        let body = "<p>TODO</p>\n<p>fine</p>\r\n<p>{{ name }}\n</p>\n\nTODO: more";
+        assert_eq!(
+            content(&rule, StatusCode::OK, body),
+            [
+                r#"contains "TODO" on line 1, matching TODO"#,
+                r#"contains "TODO" on line 6, matching TODO"#,
+                r#"contains "{{ name }}" on line 3, matching \{\{.*?\}\}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn multiline_match() {
+        let rule = rule(&[], &[r"(?s)Traceback.*Error"]);
+        assert_eq!(
+            content(
+                &rule,
+                StatusCode::OK,
+                "ok\nTraceback (most recent call last):\n  x\nError"
+            ),
+            [
+                r#"contains "Traceback (most recent call last):" on line 2, matching (?s)Traceback.*Error"#
+            ]
+        );
+    }
+
+    #[test]
+    fn max_matches() {
+        let rule = rule(&[], &["x"]);
+        let findings = content(&rule, StatusCode::OK, "x\nx\nx\nx\nx\nx\nx\n");
+        assert_eq!(findings.len(), MAX_MATCHES + 1);
+        assert_eq!(
+            findings[MAX_MATCHES - 1],
+            r#"contains "x" on line 5, matching x"#
+        );
+        assert_eq!(findings[MAX_MATCHES], "2 more matches of x");
+
+        let findings = content(&rule, StatusCode::OK, "x\nx\nx\nx\nx\n");
+        assert_eq!(findings.len(), MAX_MATCHES);
+    }
+
+    #[test]
+    fn only_successful_responses() {
+        let rule = rule(&["price"], &["TODO"]);
+        assert!(content(&rule, StatusCode::NOT_FOUND, "TODO").is_empty());
+    }
+}
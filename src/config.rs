@@ -0,0 +1,154 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::{parser::ValueSource, ArgMatches};
+use regex::Regex;
+use reqwest::Url;
+use serde::{Deserialize, Deserializer};
+use tgcheck::{
+    rule::{self, Rule},
+    scope::Pattern,
+};
+
+use crate::{CmdLineArgs, ReportFormat};
+
+/// The file that is read from the working directory when no `--config` is given.
+const DEFAULT_PATH: &str = "tgcheck.toml";
+
+fn url<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Url>, D::Error> {
+    let url = String::deserialize(deserializer)?;
+    Url::parse(&url).map(Some).map_err(serde::de::Error::custom)
+}
+
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let seconds = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(seconds)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn regexes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<Regex>>, D::Error> {
+    rule::regexes(deserializer).map(Some)
+}
+
+/// The contents of a `tgcheck.toml`. Every command line option can be set here, under the name of
+/// its long flag.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    #[serde(deserialize_with = "url")]
+    base_url: Option<Url>,
+    exclude: Option<Vec<Pattern>>,
+    include: Option<Vec<Pattern>>,
+    scope: Option<Vec<String>>,
+    no_crawl: Option<Vec<Pattern>>,
+    subdomains: Option<bool>,
+    alias: Option<Vec<String>>,
+    crawl_host: Option<Vec<String>>,
+    strict_scheme: Option<bool>,
+    request_headers: Option<Vec<String>>,
+    verbose: Option<bool>,
+    max_concurrent: Option<u16>,
+    check_external: Option<bool>,
+    max_concurrent_external: Option<u16>,
+    max_concurrent_per_host: Option<usize>,
+    requests_per_second: Option<f64>,
+    sitemap: Option<bool>,
+    orphans: Option<bool>,
+    ignore_robots: Option<bool>,
+    fold_trailing_slash: Option<bool>,
+    sort_query: Option<bool>,
+    strip_param: Option<Vec<String>>,
+    max_redirects: Option<usize>,
+    retries: Option<u32>,
+    #[serde(deserialize_with = "seconds")]
+    retry_delay: Option<Duration>,
+    #[serde(deserialize_with = "seconds")]
+    shutdown_timeout: Option<Duration>,
+    soft_404: Option<bool>,
+    #[serde(deserialize_with = "regexes")]
+    soft_404_title: Option<Vec<Regex>>,
+    format: Option<ReportFormat>,
+    output: Option<PathBuf>,
+    resume: Option<PathBuf>,
+    /// Applied in order, so later rules win from earlier ones.
+    #[serde(rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+/// Overwrite the options that were not given on the command line with those from the config file.
+macro_rules! merge {
+    ($args:ident, $config:ident, $matches:ident; $($field:ident),* $(,)?) => {
+        $(
+            if let Some(value) = $config.$field.take() {
+                if $matches.value_source(stringify!($field)) != Some(ValueSource::CommandLine) {
+                    $args.$field = value;
+                }
+            }
+        )*
+    };
+}
+
+/// Options that are optional on the command line as well.
+macro_rules! merge_optional {
+    ($args:ident, $config:ident; $($field:ident),* $(,)?) => {
+        $(
+            if $args.$field.is_none() {
+                $args.$field = $config.$field.take();
+            }
+        )*
+    };
+}
+
+impl Config {
+    /// Read the config file at `path`, or `tgcheck.toml` if there is one.
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_PATH).exists() => Path::new(DEFAULT_PATH),
+            None => return Ok(Config::default()),
+        };
+
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+        toml::from_str(&text).map_err(|e| format!("could not parse {}: {e}", path.display()))
+    }
+
+    /// Fill in `args` from the file, where the command line did not set them itself.
+    pub fn apply(&mut self, args: &mut CmdLineArgs, matches: &ArgMatches) {
+        merge!(args, self, matches;
+            exclude,
+            include,
+            scope,
+            no_crawl,
+            subdomains,
+            alias,
+            crawl_host,
+            strict_scheme,
+            request_headers,
+            verbose,
+            max_concurrent,
+            check_external,
+            max_concurrent_external,
+            max_concurrent_per_host,
+            requests_per_second,
+            sitemap,
+            orphans,
+            ignore_robots,
+            fold_trailing_slash,
+            sort_query,
+            strip_param,
+            max_redirects,
+            retries,
+            retry_delay,
+            shutdown_timeout,
+            soft_404,
+            soft_404_title,
+            format,
+        );
+        merge_optional!(args, self; base_url, output, resume);
+    }
+}
@@ -0,0 +1,861 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use regex::Regex;
+use reqwest::header::HeaderMap;
+use reqwest::{redirect, ClientBuilder, Url};
+use serde::Serialize;
+use tokio::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Semaphore,
+    },
+    task::{self, JoinHandle},
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    check::{self, Check},
+    fetch::{self, FetchConfig, Frontier},
+    normalize::Normalizer,
+    robots,
+    rule::Rule,
+    scheduler::{Limits, Scheduler},
+    scope::{Decision, Pattern, Scope},
+    shutdown::Shutdown,
+    sitemap,
+    soft404::{self, Fingerprints, SoftNotFound},
+    state::StateFile,
+    FragmentLink, Link, LinkKind, Outcome, Referrer, Referrers, ResponseResult,
+};
+
+static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
+
+/// The longest a robots.txt, sitemap or soft 404 probe may take. These are requested from the
+/// dispatch loop, so a host that never answers would hold up the whole crawl.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Builder methods that each set a single option.
+macro_rules! setters {
+    ($($(#[$doc:meta])* $field:ident: $type:ty),* $(,)?) => {
+        $(
+            $(#[$doc])*
+            pub fn $field(mut self, $field: $type) -> Self {
+                self.$field = $field;
+                self
+            }
+        )*
+    };
+}
+
+/// What to crawl, and how. The defaults are those of the command line tool.
+#[derive(Debug, Clone)]
+pub struct CrawlConfig {
+    base_url: Url,
+    request_headers: HeaderMap,
+    max_concurrent: usize,
+    check_external: bool,
+    max_concurrent_external: usize,
+    max_concurrent_per_host: usize,
+    requests_per_second: f64,
+    sitemap: bool,
+    orphans: bool,
+    ignore_robots: bool,
+    fold_trailing_slash: bool,
+    sort_query: bool,
+    strip_params: Vec<String>,
+    subdomains: bool,
+    aliases: Vec<String>,
+    crawl_hosts: Vec<String>,
+    strict_scheme: bool,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    scope: Vec<String>,
+    no_crawl: Vec<Pattern>,
+    max_redirects: usize,
+    retries: u32,
+    retry_delay: Duration,
+    shutdown_timeout: Duration,
+    rules: Vec<Rule>,
+    checks: Vec<Arc<dyn Check>>,
+    soft_404: bool,
+    soft_404_titles: Vec<Regex>,
+    resume: Option<PathBuf>,
+}
+
+impl CrawlConfig {
+    /// Crawl the site that `base_url` is on, starting at `base_url`.
+    pub fn new(base_url: Url) -> CrawlConfig {
+        CrawlConfig {
+            base_url,
+            request_headers: HeaderMap::new(),
+            max_concurrent: 1000,
+            check_external: false,
+            max_concurrent_external: 16,
+            max_concurrent_per_host: 8,
+            requests_per_second: 10.,
+            sitemap: false,
+            orphans: false,
+            ignore_robots: false,
+            fold_trailing_slash: false,
+            sort_query: false,
+            strip_params: Vec::new(),
+            subdomains: false,
+            aliases: Vec::new(),
+            crawl_hosts: Vec::new(),
+            strict_scheme: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            scope: Vec::new(),
+            no_crawl: Vec::new(),
+            max_redirects: 5,
+            retries: 2,
+            retry_delay: Duration::from_secs(1),
+            shutdown_timeout: Duration::from_secs(10),
+            rules: Vec::new(),
+            checks: check::builtin(),
+            soft_404: false,
+            soft_404_titles: Vec::new(),
+            resume: None,
+        }
+    }
+
+    setters! {
+        /// Headers to send with every request.
+        request_headers: HeaderMap,
+        /// The number of pages and assets on the site to check at the same time.
+        max_concurrent: usize,
+        /// Also check links to other hosts, without crawling them.
+        check_external: bool,
+        /// The number of links to other hosts to check at the same time.
+        max_concurrent_external: usize,
+        /// The number of requests to send to a single host at the same time.
+        max_concurrent_per_host: usize,
+        /// The number of requests per second to send to a single host, lowered automatically when
+        /// the host responds slowly or with 429 or 503.
+        requests_per_second: f64,
+        /// Also check every page listed in the sitemap(s) of the site.
+        sitemap: bool,
+        /// Compare the sitemap(s) with the pages found by crawling, implies `sitemap`.
+        orphans: bool,
+        /// Also crawl URLs that robots.txt disallows, and ignore its crawl delay.
+        ignore_robots: bool,
+        /// Treat URLs with and without a trailing slash as the same page.
+        fold_trailing_slash: bool,
+        /// Treat URLs with the same query parameters in a different order as the same page.
+        sort_query: bool,
+        /// Query parameters to ignore when recognizing duplicate URLs, a trailing `*` matches any
+        /// suffix.
+        strip_params: Vec<String>,
+        /// Also crawl the subdomains of the site.
+        subdomains: bool,
+        /// Other names of the site, like `www.example.com`, crawled as part of it.
+        aliases: Vec<String>,
+        /// Other hosts to crawl along with the site.
+        crawl_hosts: Vec<String>,
+        /// Treat links to the site over the other scheme (http or https) as external links.
+        strict_scheme: bool,
+        /// Only check pages and assets on the site that match one of these patterns.
+        include: Vec<Pattern>,
+        /// Do not check URLs matching these patterns.
+        exclude: Vec<Pattern>,
+        /// Only crawl pages under these paths, like `/docs/`. Links to other pages are still
+        /// checked.
+        scope: Vec<String>,
+        /// Check pages matching these patterns, but not the links on them.
+        no_crawl: Vec<Pattern>,
+        /// The longest chain of redirects to follow before reporting an error.
+        max_redirects: usize,
+        /// How often to retry connection errors, timeouts, 429 and 5xx responses.
+        retries: u32,
+        /// The delay before the first retry, doubled for every next attempt.
+        retry_delay: Duration,
+        /// How long to wait for the requests in flight after [`Crawl::interrupt`].
+        shutdown_timeout: Duration,
+        /// Settings for the URLs under a path prefix or matching a pattern, applied in order.
+        rules: Vec<Rule>,
+        /// The checks to run on every response, the built-in status and size checks by default.
+        checks: Vec<Arc<dyn Check>>,
+        /// Request a URL that does not exist on every host, and fail the pages that look just like
+        /// the error page that comes back with a successful status.
+        soft_404: bool,
+        /// Fail the pages whose title matches one of these patterns, like `(?i)not found`.
+        soft_404_titles: Vec<Regex>,
+        /// Save progress to this file, and continue from it if it exists. It is removed when the
+        /// crawl completes.
+        resume: Option<PathBuf>,
+    }
+
+    /// Run this check on every response, along with the others.
+    pub fn check(mut self, check: impl Check + 'static) -> Self {
+        self.checks.push(Arc::new(check));
+        self
+    }
+}
+
+/// Why a URL that was found is not checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Excluded by a pattern, the scope or a rule.
+    Excluded,
+    /// Disallowed by robots.txt.
+    Robots,
+}
+
+/// Something that happened during a crawl.
+#[derive(Debug)]
+pub enum Event {
+    /// A URL is being requested.
+    Fetching { url: Url, kind: LinkKind },
+    /// A URL was found, but is not checked.
+    Skipped { url: Url, reason: SkipReason },
+    /// A URL was checked, with the pages known to link to it so far. `remaining` is the number of
+    /// URLs that are still queued or in flight.
+    Checked {
+        result: Box<ResponseResult>,
+        referrers: Vec<Referrer>,
+        remaining: usize,
+    },
+    /// Something worth mentioning, like the number of pages in a sitemap.
+    Info(String),
+    /// Something went wrong outside of checking a URL, like saving the state file.
+    Error(String),
+}
+
+/// Pages in the sitemap that no page links to, and pages that are linked (and exist) but are
+/// missing from the sitemap.
+#[derive(Debug, Default)]
+pub struct Orphans {
+    pub unlinked: Vec<String>,
+    pub unlisted: Vec<String>,
+}
+
+impl Orphans {
+    fn new(in_sitemap: &HashSet<Url>, linked: &HashSet<Url>, pages: &HashSet<String>) -> Orphans {
+        let mut unlinked: Vec<String> = in_sitemap
+            .difference(linked)
+            .map(|url| url.to_string())
+            .collect();
+        let mut unlisted: Vec<String> = linked
+            .difference(in_sitemap)
+            .map(|url| url.to_string())
+            .filter(|url| pages.contains(url))
+            .collect();
+        unlinked.sort_unstable();
+        unlisted.sort_unstable();
+
+        Orphans { unlinked, unlisted }
+    }
+}
+
+/// Totals for the whole crawl.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub host: String,
+    pub duration_secs: f64,
+    pub pages: usize,
+    pub errors: usize,
+    pub warnings: usize,
+    pub flaky: usize,
+    pub external: usize,
+    pub external_errors: usize,
+    pub broken_anchors: Vec<FragmentLink>,
+    /// Whether the crawl was stopped early, by [`Crawl::interrupt`].
+    pub interrupted: bool,
+    /// URLs that were found but not checked, because the crawl was interrupted.
+    pub unchecked: Vec<String>,
+    /// The URLs that failed the check, internal and external.
+    #[serde(skip)]
+    pub failed: Vec<String>,
+    #[serde(skip)]
+    pub referrers: Referrers,
+    /// Only when asked for, and when the crawl was not interrupted.
+    #[serde(skip)]
+    pub orphans: Option<Orphans>,
+}
+
+#[derive(Debug, Default)]
+struct ResultState {
+    count: usize,
+    error_count: usize,
+    anchors: HashMap<String, HashSet<String>>,
+    fragments: Vec<FragmentLink>,
+    /// Internal pages that were fetched successfully.
+    pages: HashSet<String>,
+    warning_count: usize,
+    /// Successful results that needed more than one attempt.
+    flaky_count: usize,
+    external_count: usize,
+    external_error_count: usize,
+    broken_anchors: Vec<FragmentLink>,
+    /// The URLs that failed the check, internal and external.
+    failed: Vec<String>,
+}
+
+impl ResultState {
+    fn count(&mut self, result: &ResponseResult) {
+        let external = result.kind == LinkKind::External;
+
+        self.count += 1;
+        if external {
+            self.external_count += 1;
+        }
+
+        match result.outcome() {
+            Outcome::Ok => {}
+            Outcome::Warning => {
+                self.warning_count += 1;
+                if result.retried {
+                    self.flaky_count += 1;
+                }
+            }
+            Outcome::Error if external => {
+                self.external_error_count += 1;
+                self.failed.push(result.url.clone());
+            }
+            Outcome::Error => {
+                self.error_count += 1;
+                self.failed.push(result.url.clone());
+            }
+        }
+    }
+
+    /// Find the links to anchors that do not exist on their (successfully parsed) target page.
+    fn check_anchors(&mut self) {
+        for link in std::mem::take(&mut self.fragments) {
+            let Some(anchors) = self.anchors.get(&link.target) else {
+                continue;
+            };
+
+            if !anchors.contains(&link.fragment) {
+                self.error_count += 1;
+                self.broken_anchors.push(link);
+            }
+        }
+    }
+}
+
+/// Checks a site, see [`CrawlConfig`] for the options.
+#[derive(Debug)]
+pub struct Crawler {
+    config: CrawlConfig,
+}
+
+impl Crawler {
+    pub fn new(config: CrawlConfig) -> Crawler {
+        Crawler { config }
+    }
+
+    /// Start crawling in the background. This only fails when the state file to resume from
+    /// cannot be opened.
+    pub fn start(self) -> io::Result<Crawl> {
+        let (state_file, resumed) = match &self.config.resume {
+            Some(path) => {
+                let (state_file, resumed) = StateFile::open(path)?;
+                (Some(state_file), resumed)
+            }
+            None => (None, Vec::new()),
+        };
+
+        let (events_tx, events) = mpsc::channel(512);
+        let shutdown = Arc::new(Shutdown::default());
+        let handle = task::spawn(crawl(
+            self.config,
+            events_tx,
+            shutdown.clone(),
+            state_file,
+            resumed,
+        ));
+
+        Ok(Crawl {
+            events,
+            shutdown,
+            handle,
+        })
+    }
+
+    /// Crawl the whole site, and only return the summary.
+    pub async fn run(self) -> io::Result<Summary> {
+        Ok(self.start()?.finish().await)
+    }
+}
+
+/// A crawl that is running in the background.
+#[derive(Debug)]
+pub struct Crawl {
+    events: Receiver<Event>,
+    shutdown: Arc<Shutdown>,
+    handle: JoinHandle<Summary>,
+}
+
+impl Crawl {
+    /// The next event, or `None` when the crawl is done.
+    pub async fn next(&mut self) -> Option<Event> {
+        self.events.recv().await
+    }
+
+    /// Stop crawling: nothing new is requested anymore, and the requests in flight get the
+    /// shutdown timeout to finish.
+    pub fn interrupt(&self) {
+        self.shutdown.interrupt();
+    }
+
+    /// Wait for the crawl to end, skipping any events that were not read yet.
+    pub async fn finish(mut self) -> Summary {
+        while self.events.recv().await.is_some() {}
+        self.handle.await.unwrap()
+    }
+}
+
+async fn crawl(
+    config: CrawlConfig,
+    events: Sender<Event>,
+    shutdown: Arc<Shutdown>,
+    mut state_file: Option<StateFile>,
+    resumed: Vec<ResponseResult>,
+) -> Summary {
+    let start = Instant::now();
+    let url = config.base_url.clone();
+
+    let client_builder = || {
+        ClientBuilder::new()
+            .connect_timeout(Duration::from_secs(15))
+            .danger_accept_invalid_certs(true)
+            .default_headers(config.request_headers.clone())
+            .user_agent(APP_USER_AGENT)
+    };
+    // Redirects are followed by hand when checking, so every hop can be reported
+    let client = client_builder()
+        .redirect(redirect::Policy::none())
+        .build()
+        .unwrap();
+    let discovery_client = client_builder().timeout(DISCOVERY_TIMEOUT).build().unwrap();
+    let fingerprints = Fingerprints::default();
+    let mut checks = config.checks;
+    if config.soft_404 || !config.soft_404_titles.is_empty() {
+        checks.push(Arc::new(SoftNotFound {
+            fingerprints: fingerprints.clone(),
+            titles: config.soft_404_titles,
+        }));
+    }
+    let fetch_config = Arc::new(FetchConfig {
+        max_redirects: config.max_redirects,
+        retries: config.retries,
+        retry_delay: config.retry_delay,
+        rules: config.rules,
+        checks,
+    });
+
+    let todo = Arc::new(AtomicUsize::new(0));
+
+    let (tx, mut rx) = mpsc::channel::<Option<Link>>(512);
+    let (result_tx, mut result_rx) = mpsc::channel::<ResponseResult>(512);
+    let lowercase = |hosts: &[String]| hosts.iter().map(|h| h.to_ascii_lowercase()).collect();
+    // Aliases, and the other scheme unless it is another site, are the same pages
+    let normalizer = Arc::new(Normalizer {
+        fold_trailing_slash: config.fold_trailing_slash,
+        sort_query: config.sort_query,
+        strip_params: config.strip_params,
+        host: url.host_str().unwrap_or_default().to_owned(),
+        aliases: lowercase(&config.aliases),
+        scheme: (!config.strict_scheme).then(|| url.scheme().to_owned()),
+    });
+    let scope = Arc::new(Scope {
+        host: url.host_str().unwrap_or_default().to_owned(),
+        scheme: url.scheme().to_owned(),
+        aliases: lowercase(&config.aliases),
+        subdomains: config.subdomains,
+        hosts: lowercase(&config.crawl_hosts),
+        strict_scheme: config.strict_scheme,
+        include: config.include,
+        exclude: config.exclude,
+        prefixes: config.scope,
+        no_crawl: config.no_crawl,
+    });
+    let frontier = Frontier {
+        tx: tx.clone(),
+        todo: todo.clone(),
+        normalizer: normalizer.clone(),
+        scope: scope.clone(),
+    };
+
+    let start_link = Link {
+        url: url.clone(),
+        from: url.clone(),
+        kind: LinkKind::Page,
+        text: String::new(),
+        line: None,
+    };
+    frontier.push(start_link).await;
+
+    let scheduler = Arc::new(Scheduler::new(Limits {
+        requests_per_second: config.requests_per_second,
+        max_concurrent: config.max_concurrent_per_host,
+    }));
+
+    // Every host that is crawled has its own robots.txt, which is fetched when we first get there
+    let robots = robots::fetch(&discovery_client, &url).await;
+    let mut policies: HashMap<String, robots::Policy> = HashMap::new();
+    if !config.ignore_robots {
+        let policy = robots.policy(env!("CARGO_PKG_NAME"));
+        if let Some(crawl_delay) = policy.crawl_delay {
+            scheduler.set_crawl_delay(&url, crawl_delay);
+        }
+        policies.insert(url.origin().ascii_serialization(), policy);
+    }
+
+    let sitemap_pages: Vec<(Url, Url)> = if config.sitemap || config.orphans {
+        sitemap::discover(&discovery_client, &url, &scope, &robots.sitemaps, &events)
+            .await
+            .into_iter()
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let sitemaps: HashSet<Url> = sitemap_pages.iter().map(|(_, s)| s.clone()).collect();
+    let in_sitemap: HashSet<Url> = sitemap_pages
+        .iter()
+        .map(|(page, _)| normalizer.normalize(page.clone()))
+        .collect();
+
+    if !sitemap_pages.is_empty() {
+        // Count all seeds up front, the first pages may well be done before the last is queued
+        todo.fetch_add(sitemap_pages.len(), Ordering::SeqCst);
+        let pages = sitemap_pages;
+        let seed_tx = tx.clone();
+        task::spawn(async move {
+            for (page, sitemap) in pages {
+                let link = Link {
+                    url: page,
+                    from: sitemap,
+                    kind: LinkKind::Page,
+                    text: String::new(),
+                    line: None,
+                };
+                if seed_tx.send(Some(link)).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    // The first spelling of every normalized URL, which is the one that is requested
+    let mut spellings: HashMap<Url, Url> = HashMap::new();
+    let mut seen: HashSet<Url> = HashSet::new();
+    for url in resumed
+        .iter()
+        .filter_map(|result| Url::parse(&result.url).ok())
+    {
+        let key = normalizer.normalize(url.clone());
+        spellings.insert(key.clone(), url);
+        seen.insert(key);
+    }
+
+    if !resumed.is_empty() {
+        let message = format!("resuming after {} results", resumed.len());
+        let _ = events.send(Event::Info(message)).await;
+        // The earlier results count as pending up front too, and so do the links found on them
+        // before they are passed on, so the crawl cannot look finished halfway through the replay
+        todo.fetch_add(resumed.len(), Ordering::SeqCst);
+        let replay_frontier = frontier.clone();
+        let replay_tx = result_tx.clone();
+        task::spawn(async move {
+            for mut result in resumed {
+                for link in std::mem::take(&mut result.links) {
+                    replay_frontier.push(link).await;
+                }
+                result.resumed = true;
+                if replay_tx.send(result).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let output_tx = tx.clone();
+    let output_todo = todo.clone();
+    let referrers = Arc::new(Mutex::new(Referrers::new()));
+    let output_referrers = referrers.clone();
+    let output_shutdown = shutdown.clone();
+    let output_events = events.clone();
+    let output_normalizer = normalizer.clone();
+
+    let handle = task::spawn(async move {
+        let mut state = ResultState::default();
+
+        loop {
+            let mut result = tokio::select! {
+                result = result_rx.recv() => match result {
+                    Some(result) => result,
+                    None => break,
+                },
+                _ = output_shutdown.stopped() => break,
+            };
+            let todo_value = output_todo.fetch_sub(1, Ordering::SeqCst) - 1;
+            output_shutdown.done(&result.url);
+
+            if result.interrupted {
+                output_shutdown.unchecked(&result.url);
+                if todo_value == 0 {
+                    break;
+                }
+                continue;
+            }
+
+            if let Some(state_file) = state_file.as_mut().filter(|_| !result.resumed) {
+                if let Err(e) = state_file.record(&result) {
+                    let message = format!("could not save the state: {e}");
+                    let _ = output_events.send(Event::Error(message)).await;
+                }
+            }
+
+            let key = output_normalizer.normalize_str(&result.url);
+            if let Some(anchors) = result.anchors.take() {
+                state.anchors.insert(key.clone(), anchors);
+            }
+            state.fragments.append(&mut result.fragments);
+            if result.kind == LinkKind::Page && result.status.is_some_and(|s| s.is_success()) {
+                state.pages.insert(key);
+            }
+            state.count(&result);
+
+            let referrers = output_referrers.lock().unwrap().get(&result.url).cloned();
+            let event = Event::Checked {
+                result: Box::new(result),
+                referrers: referrers.unwrap_or_default(),
+                remaining: todo_value,
+            };
+            let _ = output_events.send(event).await;
+
+            if todo_value == 0 {
+                break;
+            }
+        }
+
+        // The dispatch loop may have stopped already, so do not wait for room in the queue
+        let _ = output_tx.try_send(None);
+        result_rx.close();
+        state.check_anchors();
+
+        if let Some(state_file) = state_file.filter(|_| !output_shutdown.is_interrupted()) {
+            if let Err(e) = state_file.remove() {
+                let message = format!("could not remove the state file: {e}");
+                let _ = output_events.send(Event::Error(message)).await;
+            }
+        }
+
+        state
+    });
+
+    // The start page is where the crawl enters the site, so it does not need a link
+    let mut linked = HashSet::from([normalizer.normalize(url.clone())]);
+    let sem = Arc::new(Semaphore::new(config.max_concurrent));
+    let external_sem = Arc::new(Semaphore::new(config.max_concurrent_external));
+    let mut deadline: Option<Instant> = None;
+
+    loop {
+        let link = tokio::select! {
+            link = rx.recv() => match link {
+                Some(Some(link)) => link,
+                _ => break,
+            },
+            _ = shutdown.interrupted(), if deadline.is_none() => {
+                deadline = Some(Instant::now() + config.shutdown_timeout);
+                continue;
+            }
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                let message = "stopped waiting for the requests in flight".to_owned();
+                let _ = events.send(Event::Error(message)).await;
+                shutdown.stop();
+                break;
+            }
+        };
+
+        let key = normalizer.normalize(link.url.clone());
+        let spelling = spellings
+            .entry(key.clone())
+            .or_insert_with(|| link.url.clone())
+            .to_string();
+        let self_link = normalizer.normalize(link.from.clone()) == key;
+        // Links from a page to itself, like skip links to #main, do not make it any less orphaned
+        if !sitemaps.contains(&link.from) && !self_link {
+            linked.insert(key.clone());
+        }
+        if !self_link {
+            referrers
+                .lock()
+                .unwrap()
+                .entry(spelling)
+                .or_default()
+                .push(Referrer {
+                    page: link.from.to_string(),
+                    text: link.text.clone(),
+                    line: link.line,
+                });
+        }
+
+        let origin = link.url.origin().ascii_serialization();
+        if link.kind != LinkKind::External
+            && !config.ignore_robots
+            && !policies.contains_key(&origin)
+        {
+            let robots = robots::fetch(&discovery_client, &link.url).await;
+            let policy = robots.policy(env!("CARGO_PKG_NAME"));
+            if let Some(crawl_delay) = policy.crawl_delay {
+                scheduler.set_crawl_delay(&link.url, crawl_delay);
+            }
+            policies.insert(origin.clone(), policy);
+        }
+
+        // The error page of a host is fingerprinted before any of its pages are checked
+        if link.kind != LinkKind::External
+            && config.soft_404
+            && !fingerprints.lock().unwrap().contains_key(&origin)
+        {
+            let fingerprint = soft404::fingerprint(&discovery_client, &link.url).await;
+            fingerprints
+                .lock()
+                .unwrap()
+                .insert(origin.clone(), fingerprint);
+        }
+
+        // The start page is always crawled
+        let decision = match link.from == link.url {
+            true => Decision::Crawl,
+            false => scope.decide(&link.url, link.kind),
+        };
+
+        let reason = if decision == Decision::Skip
+            || fetch_config
+                .rules
+                .iter()
+                .any(|rule| rule.exclude && rule.matches(&link.url, None))
+        {
+            Some(SkipReason::Excluded)
+        } else if link.kind != LinkKind::External
+            && policies
+                .get(&origin)
+                .is_some_and(|policy| !policy.is_allowed(&link.url))
+        {
+            Some(SkipReason::Robots)
+        } else {
+            None
+        };
+
+        let skip = if link.kind == LinkKind::External && !config.check_external {
+            true
+        } else if let Some(reason) = reason {
+            let event = Event::Skipped {
+                url: link.url.clone(),
+                reason,
+            };
+            let _ = events.send(event).await;
+            true
+        } else {
+            !seen.insert(key)
+        };
+
+        if skip || deadline.is_some() {
+            if !skip {
+                shutdown.unchecked(link.url.as_str());
+            }
+            // No result will come in for this link, so this may have been the last one
+            if todo.fetch_sub(1, Ordering::SeqCst) == 1 {
+                break;
+            }
+            continue;
+        }
+
+        shutdown.dispatched(link.url.as_str());
+
+        let inner_frontier = frontier.clone();
+        let inner_result_tx = result_tx.clone();
+        // A request that is still in flight when we give up on it should not keep the crawl open
+        let inner_events = events.downgrade();
+        let client = client.clone();
+        let fetch_config = fetch_config.clone();
+        let scheduler = scheduler.clone();
+        let shutdown = shutdown.clone();
+        let fetching = Event::Fetching {
+            url: link.url.clone(),
+            kind: link.kind,
+        };
+
+        // Other hosts get their own overall concurrency limit and do not hold up the crawl
+        if link.kind == LinkKind::External {
+            let external_sem = external_sem.clone();
+            task::spawn(async move {
+                let permit = external_sem.acquire_owned().await.unwrap();
+                let host_permit = scheduler.acquire(&link.url).await;
+                let result = if shutdown.is_interrupted() {
+                    fetch::not_checked(&link)
+                } else {
+                    if let Some(events) = inner_events.upgrade() {
+                        let _ = events.send(fetching).await;
+                    }
+                    fetch::fetch_external(link, client, fetch_config, permit, host_permit).await
+                };
+                // The output task is gone when we gave up waiting for this result
+                let _ = inner_result_tx.send(result).await;
+            });
+            continue;
+        }
+
+        let sem = sem.clone();
+        task::spawn(async move {
+            let permit = sem.acquire_owned().await.unwrap();
+            let host_permit = scheduler.acquire(&link.url).await;
+            if shutdown.is_interrupted() {
+                let _ = inner_result_tx.send(fetch::not_checked(&link)).await;
+                return;
+            }
+            if let Some(events) = inner_events.upgrade() {
+                let _ = events.send(fetching).await;
+            }
+            let crawl = decision == Decision::Crawl;
+            let result = fetch::fetch(
+                link,
+                crawl,
+                inner_frontier,
+                client,
+                fetch_config,
+                permit,
+                host_permit,
+            )
+            .await;
+            let _ = inner_result_tx.send(result).await;
+        });
+    }
+
+    // Let the output task know there is nothing left when the last link was skipped
+    drop(result_tx);
+    let state = handle.await.unwrap();
+    let interrupted = shutdown.is_interrupted();
+    let referrers = std::mem::take(&mut *referrers.lock().unwrap());
+
+    Summary {
+        host: url.host_str().unwrap_or_default().to_owned(),
+        duration_secs: start.elapsed().as_secs_f64(),
+        pages: state.count - state.external_count,
+        errors: state.error_count,
+        warnings: state.warning_count,
+        flaky: state.flaky_count,
+        external: state.external_count,
+        external_errors: state.external_error_count,
+        broken_anchors: state.broken_anchors,
+        interrupted,
+        unchecked: shutdown.take_unchecked(),
+        failed: state.failed,
+        referrers,
+        orphans: (config.orphans && !interrupted)
+            .then(|| Orphans::new(&in_sitemap, &linked, &state.pages)),
+    }
+}
@@ -0,0 +1,529 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use percent_encoding::percent_decode_str;
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE, LOCATION, RETRY_AFTER};
+use reqwest::{Client, Method, Response, StatusCode, Url};
+use tokio::{
+    sync::{mpsc::Sender, OwnedSemaphorePermit},
+    time::{sleep, Instant},
+};
+
+use crate::{
+    check::{self, Check},
+    html,
+    normalize::Normalizer,
+    rule::Rule,
+    scheduler::HostPermit,
+    scope::Scope,
+    FragmentLink, Link, LinkKind, Redirect, ResponseResult,
+};
+
+/// The sending side of the queue of links to check. Links count as pending from the moment they are
+/// queued, so the crawl cannot be considered finished while some are still waiting to be dispatched.
+/// URLs are requested as linked, the dispatcher only normalizes them to recognize duplicates.
+#[derive(Debug, Clone)]
+pub struct Frontier {
+    pub tx: Sender<Option<Link>>,
+    pub todo: Arc<AtomicUsize>,
+    pub normalizer: Arc<Normalizer>,
+    pub scope: Arc<Scope>,
+}
+
+impl Frontier {
+    pub async fn push(&self, mut link: Link) {
+        link.url.set_fragment(None);
+        self.todo.fetch_add(1, Ordering::SeqCst);
+        // The dispatch loop is gone when we gave up waiting for the requests in flight
+        let _ = self.tx.send(Some(link)).await;
+    }
+}
+
+/// Settings that apply to every request.
+#[derive(Debug)]
+pub struct FetchConfig {
+    pub max_redirects: usize,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub rules: Vec<Rule>,
+    pub checks: Vec<Arc<dyn Check>>,
+}
+
+impl FetchConfig {
+    /// The rules that match the response for `url`.
+    fn rules(&self, url: &Url, headers: &HeaderMap) -> Vec<&Rule> {
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(url, content_type))
+            .collect()
+    }
+
+    /// Whether the body of a response that is not an HTML page should be read anyway, because a
+    /// rule or a check looks at it. `result` has the status and timing, but no size yet.
+    fn needs_body(&self, url: &Url, headers: &HeaderMap, result: &ResponseResult) -> bool {
+        let Some(status) = result.status else {
+            return false;
+        };
+        let rules = self.rules(url, headers);
+        let response = check::Response {
+            url,
+            kind: result.kind,
+            status,
+            headers,
+            body: None,
+            size: None,
+            title: None,
+            duration: result.duration,
+            rules: &rules,
+        };
+
+        rules.iter().any(|rule| rule.checks_content())
+            || self.checks.iter().any(|check| check.needs_body(&response))
+    }
+
+    /// Run the checks on the response for `url`, with the rules that match it.
+    fn check(
+        &self,
+        url: &Url,
+        headers: &HeaderMap,
+        body: Option<&str>,
+        title: Option<&str>,
+        result: &mut ResponseResult,
+    ) {
+        let Some(status) = result.status else {
+            return;
+        };
+        let rules = self.rules(url, headers);
+        let response = check::Response {
+            url,
+            kind: result.kind,
+            status,
+            headers,
+            body,
+            size: result.size,
+            title,
+            duration: result.duration,
+            rules: &rules,
+        };
+
+        result.findings = check::run(&self.checks, &response);
+    }
+}
+
+/// The longest we are willing to wait when a server asks us to come back later.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+fn response_status(response: &Result<Response, reqwest::Error>) -> Option<StatusCode> {
+    match response {
+        Ok(response) => Some(response.status()),
+        Err(error) => error.status(),
+    }
+}
+
+/// Whether a failed request might succeed when tried again.
+fn is_transient(response: &Result<Response, reqwest::Error>) -> bool {
+    match response {
+        Ok(response) => {
+            response.status() == StatusCode::TOO_MANY_REQUESTS
+                || response.status().is_server_error()
+        }
+        Err(error) => error.is_connect() || error.is_timeout(),
+    }
+}
+
+/// The delay requested by a `Retry-After` header, either in seconds or as an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()?
+            .duration_since(SystemTime::now())
+            .unwrap_or_default(),
+    };
+
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+/// Find the links and assets on a page that was served from `location` after following any
+/// redirects. Relative references are resolved like a browser would: against the `<base href>` if
+/// the page has one, otherwise against the final URL of the page.
+fn extract_urls<'a>(
+    document: &'a html::Document,
+    location: &Url,
+    scope: &Scope,
+) -> Vec<(Url, LinkKind, &'a html::Reference)> {
+    let base = match document
+        .base
+        .as_deref()
+        .map(|href| location.join(href.trim()))
+    {
+        Some(Ok(base)) => base,
+        _ => location.clone(),
+    };
+    let resolve = |kind| {
+        let base = &base;
+        move |reference: &'a html::Reference| {
+            Some((base.join(reference.href.trim()).ok()?, kind, reference))
+        }
+    };
+
+    let links = document.links.iter().filter_map(resolve(LinkKind::Page));
+    let assets = document.assets.iter().filter_map(resolve(LinkKind::Asset));
+
+    links
+        .chain(assets)
+        .filter(|(url, _, _)| matches!(url.scheme(), "http" | "https"))
+        .map(|(url, kind, reference)| match scope.is_internal(&url) {
+            true => (url, kind, reference),
+            false => (url, LinkKind::External, reference),
+        })
+        .collect()
+}
+
+/// Whether a fragment should exist as an anchor on the target page. The empty fragment and `#top`
+/// always scroll to the top of the page, and text fragments are not anchors at all.
+fn is_anchor(fragment: &str) -> bool {
+    !fragment.is_empty() && !fragment.eq_ignore_ascii_case("top") && !fragment.starts_with(":~:")
+}
+
+/// Send a request and follow any redirects by hand, recording every hop in `result`. Redirect loops,
+/// overly long chains and redirects from HTTPS to HTTP are reported as errors.
+async fn send(
+    client: &Client,
+    method: Method,
+    url: Url,
+    config: &FetchConfig,
+    result: &mut ResponseResult,
+) -> Result<Response, reqwest::Error> {
+    let mut visited = HashSet::from([url.clone()]);
+    let mut current = url;
+
+    loop {
+        let response = client
+            .request(method.clone(), current.clone())
+            .send()
+            .await?;
+        let status = response.status();
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| current.join(value).ok());
+
+        let Some(location) = location.filter(|_| status.is_redirection()) else {
+            return Ok(response);
+        };
+
+        result.redirects.push(Redirect {
+            status,
+            location: location.to_string(),
+        });
+
+        if current.scheme() == "https" && location.scheme() == "http" {
+            result.error = Some(format!("redirect from HTTPS to HTTP: {location}"));
+        }
+        if !visited.insert(location.clone()) {
+            result.error = Some(format!("redirect loop at {location}"));
+            return Ok(response);
+        }
+        if result.redirects.len() > config.max_redirects {
+            result.error = Some(format!(
+                "more than {} redirects, stopped at {location}",
+                config.max_redirects
+            ));
+            return Ok(response);
+        }
+
+        current = location;
+    }
+}
+
+/// Like [`send`], but retry transient failures with an exponential backoff (with jitter), or as long
+/// as the server asks us to wait in its `Retry-After` header. Every attempt is reported to the
+/// scheduler, and retries wait for their turn like any other request to the host.
+async fn send_with_retries(
+    client: &Client,
+    method: Method,
+    url: Url,
+    config: &FetchConfig,
+    host_permit: &HostPermit,
+    result: &mut ResponseResult,
+) -> Result<Response, reqwest::Error> {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        result.attempts += 1;
+        result.redirects.clear();
+        result.error = None;
+
+        let start = Instant::now();
+        let response = send(client, method.clone(), url.clone(), config, result).await;
+        host_permit.report(response_status(&response), start.elapsed());
+        if !is_transient(&response) {
+            if attempts > 1 && response.as_ref().is_ok_and(|r| r.status().is_success()) {
+                result
+                    .warnings
+                    .push(format!("succeeded after {attempts} attempts"));
+                result.retried = true;
+            }
+            return response;
+        }
+        if attempts > config.retries {
+            return response;
+        }
+
+        let backoff = config
+            .retry_delay
+            .saturating_mul(2u32.saturating_pow(attempts - 1));
+        let delay = match &response {
+            Ok(response) => retry_after(response),
+            Err(_) => None,
+        }
+        .unwrap_or_else(|| backoff.mul_f64(0.5 + fastrand::f64()));
+
+        sleep(delay).await;
+        host_permit.throttle().await;
+    }
+}
+
+/// The result for a URL that was dispatched, but not requested before the crawl was interrupted.
+pub fn not_checked(link: &Link) -> ResponseResult {
+    ResponseResult {
+        from: link.from.as_str().to_owned(),
+        url: link.url.as_str().to_owned(),
+        kind: link.kind,
+        interrupted: true,
+        ..Default::default()
+    }
+}
+
+/// The size of a body that is not used, from the `Content-Length` header if there is one, or by
+/// reading it without keeping it.
+async fn body_size(mut response: Response) -> Result<usize, reqwest::Error> {
+    if let Some(length) = response.content_length() {
+        return Ok(length as usize);
+    }
+
+    let mut size = 0;
+    while let Some(chunk) = response.chunk().await? {
+        size += chunk.len();
+    }
+    Ok(size)
+}
+
+/// Check a page or asset on the site. The links on a page are queued when `crawl` is set.
+pub async fn fetch(
+    link: Link,
+    crawl: bool,
+    frontier: Frontier,
+    client: Client,
+    config: Arc<FetchConfig>,
+    fetch_permit: OwnedSemaphorePermit,
+    host_permit: HostPermit,
+) -> ResponseResult {
+    let Link {
+        url, from, kind, ..
+    } = link;
+    let mut result = ResponseResult {
+        from: from.as_str().to_owned(),
+        url: url.as_str().to_owned(),
+        kind,
+        ..Default::default()
+    };
+
+    let start = Instant::now();
+    let possible_response = send_with_retries(
+        &client,
+        Method::GET,
+        url.clone(),
+        &config,
+        &host_permit,
+        &mut result,
+    )
+    .await;
+    drop(fetch_permit);
+    drop(host_permit);
+
+    let response = match possible_response {
+        Ok(response) => response,
+        Err(error) => {
+            result.status = error.status();
+            result.error = Some(error.to_string());
+            result.duration = start.elapsed();
+
+            return result;
+        }
+    };
+    result.duration = start.elapsed();
+    result.status = Some(response.status());
+    // Links on pages (and in sitemaps) should point to the final URL directly
+    if !result.redirects.is_empty() && from != url && result.error.is_none() {
+        result
+            .warnings
+            .push(format!("redirects to {}", response.url()));
+    }
+    let location = response.url().clone();
+    let headers = response.headers().clone();
+    let is_html = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_none_or(|value| value.contains("html"));
+
+    // Anything but an HTML page is only read in full when a rule or check needs it, so that a
+    // linked video is not kept in memory just to check its status
+    if kind == LinkKind::Asset || !is_html {
+        let possible_body = match config.needs_body(&url, &headers, &result) {
+            true => response.bytes().await.map(|bytes| {
+                let body = String::from_utf8_lossy(&bytes).into_owned();
+                (bytes.len(), Some(body))
+            }),
+            false => body_size(response).await.map(|size| (size, None)),
+        };
+        match possible_body {
+            Ok((size, body)) => {
+                result.size = Some(size);
+                config.check(&url, &headers, body.as_deref(), None, &mut result);
+            }
+            Err(error) => {
+                result.status = error.status();
+                result.error = Some(error.to_string());
+            }
+        }
+        return result;
+    }
+
+    let bytes = match response.bytes().await {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            result.status = error.status();
+            result.error = Some(error.to_string());
+            return result;
+        }
+    };
+    result.size = Some(bytes.len());
+    let body = String::from_utf8_lossy(&bytes);
+    let document = html::parse(&body);
+    let title = document.title.as_deref();
+    config.check(&url, &headers, Some(&body), title, &mut result);
+    // Pages out of scope still get their anchors recorded for the links pointing to them
+    let urls = match crawl {
+        true => extract_urls(&document, &location, &frontier.scope),
+        false => Vec::new(),
+    };
+    let count = urls.len();
+
+    for (target, kind, reference) in urls {
+        let fragment = target.fragment().map(|fragment| {
+            percent_decode_str(fragment)
+                .decode_utf8_lossy()
+                .into_owned()
+        });
+
+        if let Some(fragment) = fragment {
+            if kind == LinkKind::Page && is_anchor(&fragment) {
+                // Anchors are looked up by the normalized URL of the page they are on
+                let page = frontier.normalizer.normalize(target.clone());
+                result.fragments.push(FragmentLink {
+                    from: url.as_str().to_owned(),
+                    line: reference.line,
+                    target: page.as_str().to_owned(),
+                    fragment,
+                });
+            }
+        }
+
+        let link = Link {
+            url: target,
+            from: url.clone(),
+            kind,
+            text: reference.text.clone(),
+            line: Some(reference.line),
+        };
+        result.links.push(link.clone());
+        frontier.push(link).await;
+    }
+
+    result.anchors = Some(document.anchors);
+    if count > 0 {
+        result.message = Some(format!("{count} URL's found"));
+    }
+
+    result
+}
+
+/// Check a URL on another host. The body is never used, so a HEAD request is enough, but servers
+/// that do not handle HEAD properly get a second chance with a GET.
+pub async fn fetch_external(
+    link: Link,
+    client: Client,
+    config: Arc<FetchConfig>,
+    fetch_permit: OwnedSemaphorePermit,
+    host_permit: HostPermit,
+) -> ResponseResult {
+    let mut result = ResponseResult {
+        from: link.from.as_str().to_owned(),
+        url: link.url.as_str().to_owned(),
+        kind: link.kind,
+        ..Default::default()
+    };
+
+    let start = Instant::now();
+    result.attempts += 1;
+    let head = send(
+        &client,
+        Method::HEAD,
+        link.url.clone(),
+        &config,
+        &mut result,
+    )
+    .await;
+    host_permit.report(response_status(&head), start.elapsed());
+
+    let possible_response = match head {
+        Ok(response) if response.status().is_success() => Ok(response),
+        _ => {
+            host_permit.throttle().await;
+            send_with_retries(
+                &client,
+                Method::GET,
+                link.url.clone(),
+                &config,
+                &host_permit,
+                &mut result,
+            )
+            .await
+        }
+    };
+    result.duration = start.elapsed();
+    drop(fetch_permit);
+    drop(host_permit);
+
+    match possible_response {
+        Ok(response) => {
+            result.status = Some(response.status());
+            // The size hint of a HEAD response is always 0, as it has no body
+            result.size = response
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok()?.parse().ok());
+            config.check(&link.url, response.headers(), None, None, &mut result);
+        }
+        Err(error) => {
+            result.status = error.status();
+            result.error = Some(error.to_string());
+        }
+    }
+
+    result
+}
@@ -0,0 +1,335 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+};
+
+use html5ever::tendril::StrTendril;
+use html5ever::tokenizer::{
+    states::RawKind, BufferQueue, Tag, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer,
+    TokenizerOpts,
+};
+
+/// A link or asset on a page, and where it can be found.
+#[derive(Debug, Default)]
+pub struct Reference {
+    /// The raw (entity-decoded, but unresolved) target.
+    pub href: String,
+    /// The text of a link, or the alt text of an image.
+    pub text: String,
+    pub line: u64,
+}
+
+/// Everything tgcheck needs to know about a fetched HTML page.
+#[derive(Debug, Default)]
+pub struct Document {
+    /// Link targets in document order.
+    pub links: Vec<Reference>,
+    /// Resources the page embeds: images, scripts, stylesheets, media and frames.
+    pub assets: Vec<Reference>,
+    /// The `href` of the first `<base>` element, which overrides the document URL for resolving.
+    pub base: Option<String>,
+    /// Element ids and `<a name>` values, the targets a `#fragment` can point to.
+    pub anchors: HashSet<String>,
+    /// The text of the first `<title>` element.
+    pub title: Option<String>,
+}
+
+#[derive(Default)]
+struct Sink {
+    document: RefCell<Document>,
+    /// The link whose text we are collecting, until its `</a>`.
+    open_link: Cell<Option<usize>>,
+    /// Whether we are collecting the text of the title, until its `</title>`.
+    in_title: Cell<bool>,
+}
+
+fn attribute<'a>(tag: &'a Tag, name: &str) -> Option<&'a str> {
+    tag.attrs
+        .iter()
+        .find(|attr| &*attr.name.local == name)
+        .map(|attr| &*attr.value)
+}
+
+/// Split a `srcset` attribute into its candidate URLs, dropping the width and density descriptors.
+/// See <https://html.spec.whatwg.org/multipage/images.html#parsing-a-srcset-attribute>.
+fn srcset_urls(srcset: &str) -> Vec<&str> {
+    let mut urls = Vec::new();
+    let mut rest = srcset;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let (candidate, remainder) = rest.split_at(end);
+        let url = candidate.trim_end_matches(',');
+        if !url.is_empty() {
+            urls.push(url);
+        }
+
+        rest = if url.len() < candidate.len() {
+            // A trailing comma directly after the URL ends the candidate without descriptors
+            remainder
+        } else {
+            // Skip the descriptors, which may contain commas between parentheses
+            let mut depth = 0usize;
+            let end = remainder
+                .find(|c: char| match c {
+                    '(' => {
+                        depth += 1;
+                        false
+                    }
+                    ')' => {
+                        depth = depth.saturating_sub(1);
+                        false
+                    }
+                    ',' => depth == 0,
+                    _ => false,
+                })
+                .unwrap_or(remainder.len());
+            &remainder[end..]
+        };
+    }
+
+    urls
+}
+
+impl Sink {
+    fn link(&self, href: Option<&str>, text: Option<&str>, line: u64) -> Option<usize> {
+        let mut document = self.document.borrow_mut();
+        document.links.push(Reference {
+            href: href?.to_owned(),
+            text: text.unwrap_or_default().to_owned(),
+            line,
+        });
+        Some(document.links.len() - 1)
+    }
+
+    fn asset(&self, src: Option<&str>, line: u64) {
+        if let Some(src) = src {
+            self.document.borrow_mut().assets.push(Reference {
+                href: src.to_owned(),
+                text: String::new(),
+                line,
+            });
+        }
+    }
+
+    fn image(&self, tag: &Tag, line: u64) {
+        let alt = attribute(tag, "alt").unwrap_or_default();
+        let srcset = attribute(tag, "srcset")
+            .map(srcset_urls)
+            .unwrap_or_default();
+
+        for src in attribute(tag, "src").into_iter().chain(srcset) {
+            self.document.borrow_mut().assets.push(Reference {
+                href: src.to_owned(),
+                text: alt.to_owned(),
+                line,
+            });
+        }
+        // An image is all the text some links have
+        self.text(alt);
+    }
+
+    fn text(&self, text: &str) {
+        if let Some(idx) = self.open_link.get() {
+            self.document.borrow_mut().links[idx].text.push_str(text);
+        }
+        if self.in_title.get() {
+            let mut document = self.document.borrow_mut();
+            document.title.get_or_insert_default().push_str(text);
+        }
+    }
+
+    fn start_tag(&self, tag: &Tag, line: u64) -> TokenSinkResult<()> {
+        let name = match &*tag.name {
+            "a" => attribute(tag, "name"),
+            _ => None,
+        };
+        for anchor in [attribute(tag, "id"), name].into_iter().flatten() {
+            self.document.borrow_mut().anchors.insert(anchor.to_owned());
+        }
+
+        match &*tag.name {
+            "a" => self
+                .open_link
+                .set(self.link(attribute(tag, "href"), None, line)),
+            "area" => {
+                self.link(attribute(tag, "href"), attribute(tag, "alt"), line);
+            }
+            "base" => {
+                let mut document = self.document.borrow_mut();
+                if document.base.is_none() {
+                    document.base = attribute(tag, "href").map(str::to_owned);
+                }
+            }
+            "img" | "source" => self.image(tag, line),
+            "video" => {
+                self.asset(attribute(tag, "src"), line);
+                self.asset(attribute(tag, "poster"), line);
+            }
+            "audio" | "track" | "embed" => self.asset(attribute(tag, "src"), line),
+            "input" if attribute(tag, "type").is_some_and(|t| t.eq_ignore_ascii_case("image")) => {
+                self.asset(attribute(tag, "src"), line)
+            }
+            "object" => self.asset(attribute(tag, "data"), line),
+            "link" => {
+                // Resource hints point at origins rather than at documents we could check
+                let rel = attribute(tag, "rel")
+                    .unwrap_or_default()
+                    .to_ascii_lowercase();
+                if !rel
+                    .split_ascii_whitespace()
+                    .any(|r| r == "preconnect" || r == "dns-prefetch")
+                {
+                    self.asset(attribute(tag, "href"), line);
+                }
+            }
+            // The tokenizer does not know about element content models by itself; without a tree
+            // builder we have to switch it into the right state, otherwise markup inside scripts
+            // and styles would be picked up as links.
+            "script" => {
+                self.asset(attribute(tag, "src"), line);
+                return TokenSinkResult::RawData(RawKind::ScriptData);
+            }
+            "iframe" => {
+                self.asset(attribute(tag, "src"), line);
+                return TokenSinkResult::RawData(RawKind::Rawtext);
+            }
+            "style" | "xmp" | "noembed" | "noframes" => {
+                return TokenSinkResult::RawData(RawKind::Rawtext)
+            }
+            "title" => {
+                self.in_title.set(self.document.borrow().title.is_none());
+                return TokenSinkResult::RawData(RawKind::Rcdata);
+            }
+            "textarea" => return TokenSinkResult::RawData(RawKind::Rcdata),
+            "plaintext" => return TokenSinkResult::Plaintext,
+            _ => {}
+        }
+
+        TokenSinkResult::Continue
+    }
+}
+
+impl TokenSink for Sink {
+    type Handle = ();
+
+    fn process_token(&self, token: Token, line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(tag) if tag.kind == TagKind::StartTag => {
+                return self.start_tag(&tag, line_number)
+            }
+            Token::TagToken(tag) if &*tag.name == "a" => self.open_link.set(None),
+            Token::TagToken(tag) if &*tag.name == "title" => self.in_title.set(false),
+            Token::CharacterTokens(text) => self.text(&text),
+            _ => {}
+        }
+
+        TokenSinkResult::Continue
+    }
+}
+
+/// Tokenize an HTML body the way a browser would and collect the parts we are interested in.
+/// Comments, script and style contents are skipped and character references are decoded.
+pub fn parse(body: &str) -> Document {
+    let input = BufferQueue::default();
+    input.push_back(StrTendril::from_slice(body));
+
+    let tokenizer = Tokenizer::new(Sink::default(), TokenizerOpts::default());
+    let _ = tokenizer.feed(&input);
+    tokenizer.end();
+
+    let mut document = tokenizer.sink.document.take();
+    for link in &mut document.links {
+        link.text = link.text.split_whitespace().collect::<Vec<_>>().join(" ");
+    }
+    if let Some(title) = &mut document.title {
+        *title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    }
+
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hrefs(document: &Document) -> Vec<&str> {
+        document
+            .links
+            .iter()
+            .map(|link| link.href.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn unquoted_href() {
+        let document = parse("<a href=/docs/a.html>docs</a><a href='b.html'>b</a>");
+        assert_eq!(hrefs(&document), ["/docs/a.html", "b.html"]);
+        assert_eq!(document.links[0].text, "docs");
+    }
+
+    #[test]
+    fn entities() {
+        let document = parse(r#"<a href="/list?a=1&amp;b=2&#38;c=3">Tom &amp; Jerry</a>"#);
+        assert_eq!(hrefs(&document), ["/list?a=1&b=2&c=3"]);
+        assert_eq!(document.links[0].text, "Tom & Jerry");
+    }
+
+    #[test]
+    fn comments() {
+        let document = parse(r#"<!-- <a href="/old">old</a> --><a href="/new">new</a>"#);
+        assert_eq!(hrefs(&document), ["/new"]);
+    }
+
+    #[test]
+    fn raw_text() {
+        let document = parse(
+            r#"<script>document.write('<a href="/script">x</a>')</script>
+            <style>a[href="/style"] { color: red }</style>
+            <textarea><a href="/textarea">x</a></textarea>
+            <title><a href="/title">x</a></title>
+            <a href="/real">real</a>"#,
+        );
+        assert_eq!(hrefs(&document), ["/real"]);
+        assert_eq!(document.title.as_deref(), Some(r#"<a href="/title">x</a>"#));
+    }
+
+    #[test]
+    fn lines() {
+        let document =
+            parse("<p>\n<a href=\"/a\">a</a>\n\n<img\n src=\"/b.png\">\n<a href=\"/c\">c</a>");
+        let lines: Vec<u64> = document.links.iter().map(|link| link.line).collect();
+        assert_eq!(lines, [2, 6]);
+        assert_eq!(document.assets[0].href, "/b.png");
+        assert_eq!(document.assets[0].line, 5);
+    }
+
+    #[test]
+    fn srcset() {
+        assert_eq!(srcset_urls("a.jpg"), ["a.jpg"]);
+        assert_eq!(srcset_urls("a.jpg 1x, b.jpg 2x"), ["a.jpg", "b.jpg"]);
+        assert_eq!(
+            srcset_urls(" a.jpg 480w,\n\tb.jpg  800w , c.jpg"),
+            ["a.jpg", "b.jpg", "c.jpg"]
+        );
+        assert_eq!(srcset_urls("a.jpg, b.jpg 2x"), ["a.jpg", "b.jpg"]);
+        assert_eq!(srcset_urls("a.jpg,, ,b.jpg"), ["a.jpg", "b.jpg"]);
+        // Commas inside a URL do not split it, only a comma right after it or after the descriptors
+        assert_eq!(
+            srcset_urls("data:image/png;base64,AAAA 1x, b.jpg 2x"),
+            ["data:image/png;base64,AAAA", "b.jpg"]
+        );
+        assert_eq!(srcset_urls("a.jpg,b.jpg"), ["a.jpg,b.jpg"]);
+        // Descriptors may contain commas between parentheses
+        assert_eq!(srcset_urls("a.jpg (x, y) 1x, b.jpg"), ["a.jpg", "b.jpg"]);
+        assert!(srcset_urls("").is_empty());
+        assert!(srcset_urls(" , ").is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+//! Crawl a site and check every page, asset and (optionally) external link on it.
+//!
+//! ```no_run
+//! use tgcheck::{CrawlConfig, Crawler, Event};
+//!
+//! # async fn check() -> std::io::Result<()> {
+//! let config = CrawlConfig::new("https://tweedegolf.nl/".parse().unwrap()).check_external(true);
+//! let mut crawl = Crawler::new(config).start()?;
+//! while let Some(event) = crawl.next().await {
+//!     if let Event::Checked { result, .. } = event {
+//!         println!("{} {:?}", result.url, result.outcome());
+//!     }
+//! }
+//! let summary = crawl.finish().await;
+//! assert_eq!(summary.errors, 0);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+
+pub mod check;
+mod crawler;
+mod fetch;
+mod html;
+mod normalize;
+pub mod report;
+mod robots;
+pub mod rule;
+mod scheduler;
+pub mod scope;
+mod shutdown;
+mod sitemap;
+mod soft404;
+mod state;
+
+use check::{Finding, Severity};
+pub use crawler::{Crawl, CrawlConfig, Crawler, Event, Orphans, SkipReason, Summary};
+
+/// Whether a URL is a page we crawl into or a resource embedded by a page that we only check.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    #[default]
+    Page,
+    Asset,
+    /// A link or asset on another host, which is checked but never parsed.
+    External,
+}
+
+impl std::fmt::Display for LinkKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkKind::Page => f.write_str("page"),
+            LinkKind::Asset => f.write_str("asset"),
+            LinkKind::External => f.write_str("external"),
+        }
+    }
+}
+
+/// A URL discovered on the page `from`, waiting to be checked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Link {
+    pub url: Url,
+    pub from: Url,
+    pub kind: LinkKind,
+    /// The link text on `from` and the line it is on, if `from` is a page.
+    pub text: String,
+    pub line: Option<u64>,
+}
+
+/// A page that links to a URL.
+#[derive(Debug, Clone, Serialize)]
+pub struct Referrer {
+    pub page: String,
+    pub text: String,
+    pub line: Option<u64>,
+}
+
+/// Every page that links to a URL, not only the one it was first found on.
+pub type Referrers = HashMap<String, Vec<Referrer>>;
+
+/// A link to `#fragment` on the page `target`, found on line `line` of the page `from`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FragmentLink {
+    pub from: String,
+    pub line: u64,
+    pub target: String,
+    pub fragment: String,
+}
+
+/// A single redirect response, pointing to `location`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Redirect {
+    #[serde(with = "state::status")]
+    pub status: StatusCode,
+    pub location: String,
+}
+
+/// Whether a result passed, passed with problems that should be fixed, or failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// The result of checking a single URL.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ResponseResult {
+    pub from: String,
+    pub url: String,
+    pub kind: LinkKind,
+    #[serde(with = "state::status::option")]
+    pub status: Option<StatusCode>,
+    pub size: Option<usize>,
+    /// The time it took to get the full response, including retries.
+    pub duration: Duration,
+    pub error: Option<String>,
+    /// Problems that do not fail the check, but should be fixed anyway.
+    pub warnings: Vec<String>,
+    /// The number of requests it took to get this result, including retries.
+    pub attempts: u32,
+    pub message: Option<String>,
+    pub redirects: Vec<Redirect>,
+    /// What the checks found wrong with the response.
+    pub findings: Vec<Finding>,
+    /// The anchors on this page, if it is an HTML page.
+    pub(crate) anchors: Option<HashSet<String>>,
+    pub(crate) fragments: Vec<FragmentLink>,
+    /// Set when the request only succeeded after it was retried.
+    #[serde(default)]
+    pub(crate) retried: bool,
+    /// The links found on this page, so a resumed crawl can queue them again.
+    pub(crate) links: Vec<Link>,
+    /// Set when the crawl was interrupted before this URL was requested.
+    #[serde(skip)]
+    pub(crate) interrupted: bool,
+    /// Set when this result was read back from the state file of an earlier run.
+    #[serde(skip)]
+    pub(crate) resumed: bool,
+}
+
+impl ResponseResult {
+    fn found(&self, check: &str) -> bool {
+        self.findings.iter().any(|finding| finding.check == check)
+    }
+
+    pub fn size_error(&self) -> bool {
+        self.found(check::Size::NAME)
+    }
+
+    pub fn status_error(&self) -> bool {
+        self.error.is_some() || self.found(check::Status::NAME)
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        let severity = self.findings.iter().map(|finding| finding.severity).max();
+        if self.error.is_some() || severity == Some(Severity::Error) {
+            Outcome::Error
+        } else if !self.warnings.is_empty() || severity == Some(Severity::Warning) {
+            Outcome::Warning
+        } else {
+            Outcome::Ok
+        }
+    }
+}
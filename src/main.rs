@@ -1,38 +1,71 @@
 use std::{
-    collections::HashSet,
-    io::Write,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
-    },
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
     time::Duration,
 };
 
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, FromArgMatches, Parser, ValueEnum};
 use colored::Colorize;
-use once_cell::sync::Lazy;
 use regex::Regex;
 use reqwest::header::{HeaderMap, HeaderName};
-use reqwest::{Client, ClientBuilder, StatusCode, Url};
-use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
-use tokio::{
-    sync::mpsc::{self, Sender},
-    task,
-    time::{sleep, Instant},
+use reqwest::Url;
+use serde::Deserialize;
+use tgcheck::{
+    check::{Severity, Status},
+    report::{self, Format},
+    scope::Pattern,
+    CrawlConfig, Crawler, Event, FragmentLink, LinkKind, Orphans, Outcome, Referrers,
+    ResponseResult, SkipReason,
 };
+use tokio::task;
 
-static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
+/// Set when a machine readable report is written to stdout. The human readable output then goes to
+/// stderr, so the two do not get mixed up.
+static REPORT_ON_STDOUT: AtomicBool = AtomicBool::new(false);
 
-const MIN_SIZE: usize = 200;
+/// Like `println!`, but out of the way of a report on stdout.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if crate::REPORT_ON_STDOUT.load(std::sync::atomic::Ordering::Relaxed) {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
 
-#[derive(Debug, Default)]
-struct ResponseResult {
-    from: String,
-    url: String,
-    status: Option<StatusCode>,
-    size: Option<usize>,
-    error: Option<String>,
-    message: Option<String>,
+mod config;
+
+use config::Config;
+
+/// Exit code for a crawl that was stopped by Ctrl-C or SIGTERM, like a shell would report.
+const INTERRUPTED: i32 = 130;
+
+/// Resolves on Ctrl-C, or on SIGTERM on Unix.
+async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.unwrap();
+}
+
+/// The path of a full URL, which is all we have room for in the terminal output.
+fn url_path(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(url) => url.path().to_owned(),
+        Err(_) => url.to_owned(),
+    }
 }
 
 fn truncate(s: String, max_chars: usize) -> String {
@@ -42,186 +75,323 @@ fn truncate(s: String, max_chars: usize) -> String {
     }
 }
 
-fn log_result(result: ResponseResult, state: &mut ResultState, todo: usize, verbose: bool) {
-    let (size_string, size_error) = match result.size {
-        Some(s) if s < MIN_SIZE => ((s / 1000).to_string().red(), true),
-        Some(s) => ((s / 1000).to_string().green(), false),
-        None => ("?".yellow(), true),
+/// What is left to print at the end, after the progress lines.
+#[derive(Debug, Default)]
+struct Progress {
+    count: usize,
+    last_len: usize,
+    external_failures: Vec<String>,
+}
+
+fn log_result(result: ResponseResult, progress: &mut Progress, todo: usize, verbose: bool) {
+    let external = result.kind == LinkKind::External;
+    let outcome = result.outcome();
+    let size_string = match result.size {
+        Some(s) if result.size_error() => s.to_string().red(),
+        Some(s) => s.to_string().green(),
+        None => "?".yellow(),
     };
 
-    let (status, status_error) = match result.status {
-        Some(status) if status.is_success() => (status.to_string().green(), false),
-        Some(status) => (status.to_string().red(), true),
-        None => ("ERROR".red(), true),
+    let status = match (result.status, outcome) {
+        (None, _) => "ERROR".red(),
+        (Some(status), _) if result.status_error() => status.to_string().red(),
+        (Some(status), Outcome::Warning) => status.to_string().yellow(),
+        (Some(status), _) => status.to_string().green(),
     };
 
-    state.count += 1;
+    progress.count += 1;
 
+    let redirects = match result.redirects.len() {
+        0 => String::new(),
+        1 => " (1 redirect)".to_owned(),
+        n => format!(" ({n} redirects)"),
+    };
+    let attempts = match result.attempts {
+        0 | 1 => String::new(),
+        n => format!(" ({n} attempts)"),
+    };
     let details = format!(
-        "[{size_string: >5} KB] {} -> {}",
-        truncate(result.from, 30),
-        truncate(result.url, 60)
+        "[{size_string: >8} B] {: <8} {} -> {}{redirects}{attempts}",
+        result.kind,
+        truncate(url_path(&result.from), 30),
+        truncate(result.url.clone(), 60)
     );
     let line = format!(
         " {: <10} {status: <13} {details}",
-        format!("[{}/{todo}]", state.count)
+        format!("[{}/{todo}]", progress.count)
     );
-    let whitespace = " ".repeat(state.last_len.saturating_sub(line.len()));
-
-    if !status_error && !size_error {
-        if verbose {
-            println!("{line}");
-        } else {
-            print!("{line}{whitespace}\r");
+    let whitespace = " ".repeat(progress.last_len.saturating_sub(line.len()));
+
+    match outcome {
+        Outcome::Warning => eprintln!("{line}{whitespace}"),
+        Outcome::Ok => {
+            if verbose {
+                info!("{line}");
+            } else if REPORT_ON_STDOUT.load(Ordering::Relaxed) {
+                eprint!("{line}{whitespace}\r");
+            } else {
+                print!("{line}{whitespace}\r");
+            }
+            progress.last_len = line.len();
         }
-        state.last_len = line.len();
-    } else {
-        eprintln!("{line}{whitespace}");
-        state.error_count += 1;
+        Outcome::Error if external => progress.external_failures.push(line),
+        Outcome::Error => eprintln!("{line}{whitespace}"),
     }
 
     if verbose {
         if let Some(m) = result.message {
-            println!("> {}", m);
+            info!("> {}", m);
         }
 
-        if let Some(r) = result.error {
-            println!("! {}", r.red());
+        for redirect in &result.redirects {
+            info!("> {} {}", redirect.status, redirect.location);
         }
     }
 
-    let _ = std::io::stdout().flush();
-}
+    if let Some(r) = result.error {
+        eprintln!("! {}", r.red());
+    }
 
-fn base_url(mut url: Url) -> Url {
-    match url.path_segments_mut() {
-        Ok(mut path) => {
-            path.clear();
-        }
-        Err(error) => panic!("{error:?}"),
-    };
+    for w in result.warnings {
+        eprintln!("! {}", w.yellow());
+    }
 
-    url.set_query(None);
+    // The status column already shows what the status check found
+    let findings = result
+        .findings
+        .iter()
+        .filter(|finding| finding.check != Status::NAME);
+    for finding in findings {
+        let message = format!("{}: {}", finding.check, finding.message);
+        match finding.severity {
+            Severity::Error => eprintln!("! {}", message.red()),
+            Severity::Warning => eprintln!("! {}", message.yellow()),
+        }
+    }
 
-    url
+    let _ = std::io::stdout().flush();
 }
 
-async fn extract_urls(body: &str, base: &Url, from: &Url, tx: Sender<Option<(Url, Url)>>) -> usize {
-    static HREF: Lazy<Regex> = Lazy::new(|| {
-        Regex::new(r#"<a\s+(?:[^>]*?\s+)?href\s*=\s*(('(?<href_a>.*?)')|("(?<href_b>.*?)"))"#)
-            .unwrap()
-    });
-    let captures = HREF
-        .captures_iter(body)
-        .filter_map(|r| r.name("href_a").or(r.name("href_b")))
-        .map(|v| v.as_str())
-        .filter_map(|href| {
-            if href.starts_with('#') {
-                return None;
-            }
+/// Report links to anchors that do not exist on their (successfully parsed) target page.
+fn log_broken_anchors(broken_anchors: &[FragmentLink], progress: &Progress) {
+    for link in broken_anchors {
+        let details = format!(
+            "{}:{} -> {}#{}",
+            truncate(url_path(&link.from), 30),
+            link.line,
+            truncate(link.target.clone(), 60),
+            link.fragment
+        );
+        let line = format!(" {: <10} {: <13} {details}", "", "BROKEN ANCHOR".red());
+        let whitespace = " ".repeat(progress.last_len.saturating_sub(line.len()));
 
-            if href.starts_with("http://") || href.starts_with("https://") {
-                Url::parse(href).ok()
-            } else {
-                base.join(href).ok()
-            }
-        })
-        .filter(|url| url.host() == base.host())
-        .collect::<Vec<Url>>();
-
-    for capture in &captures {
-        tx.send(Some((capture.clone(), from.to_owned())))
-            .await
-            .unwrap();
+        eprintln!("{line}{whitespace}");
     }
-
-    captures.len()
 }
 
-async fn fetch(
-    url: Url,
-    from: Url,
-    tx: Sender<Option<(Url, Url)>>,
-    client: Client,
-    fetch_permit: OwnedSemaphorePermit,
-    running_average_response_time: Arc<Mutex<f64>>,
-) -> ResponseResult {
-    let mut result = ResponseResult {
-        from: from.path().to_owned(),
-        url: url.as_str().to_owned(),
-        ..Default::default()
-    };
-
-    let start = Instant::now();
-    let possible_response = client.get(url.clone()).send().await;
-    drop(fetch_permit);
-    let duration = start.elapsed().as_secs_f64();
-    let mut running_average = running_average_response_time.lock().await;
-    *running_average = *running_average * (9. / 10.) + duration * (1. / 10.);
-    drop(running_average);
-
-    let possible_body = match possible_response {
-        Ok(response) => {
-            result.status = Some(response.status());
-
-            response.text().await
-        }
-        Err(error) => {
-            result.status = error.status();
-            result.error = Some(error.to_string());
+/// List every page that links to a URL that failed, so all of them can be fixed at once.
+fn log_referrers(failed: &[String], referrers: &Referrers) {
+    if failed.is_empty() {
+        return;
+    }
 
-            return result;
+    eprintln!("<<< pages linking to failed URLs");
+    for url in failed {
+        eprintln!("! {}", url.red());
+        for referrer in referrers.get(url).into_iter().flatten() {
+            let location = match referrer.line {
+                Some(line) => format!("{}:{line}", url_path(&referrer.page)),
+                None => referrer.page.clone(),
+            };
+            match referrer.text.as_str() {
+                "" => eprintln!(">   {location}"),
+                text => eprintln!(">   {location} \"{}\"", truncate(text.to_owned(), 40)),
+            }
         }
-    };
+    }
+}
 
-    match possible_body {
-        Ok(body) => {
-            result.size = Some(body.len());
-            let base = base_url(url.clone());
-            let count = extract_urls(&body, &base, &url, tx).await;
-            if count > 0 {
-                result.message = Some(format!("{count} URL's found"));
+/// Compare the sitemap with the crawl: pages in the sitemap that no page links to, and pages that
+/// are linked (and exist) but are missing from the sitemap.
+fn log_orphans(orphans: &Orphans) {
+    for (urls, description) in [
+        (
+            &orphans.unlinked,
+            "in the sitemap but not linked from any page",
+        ),
+        (&orphans.unlisted, "linked but missing from the sitemap"),
+    ] {
+        let count = format!("{} pages", urls.len());
+        info!(
+            "<<< {} {description}",
+            if urls.is_empty() {
+                count.green()
+            } else {
+                count.yellow()
             }
-        }
-        Err(error) => {
-            result.status = error.status();
-            result.error = Some(error.to_string());
+        );
+        for url in urls {
+            info!("> {url}");
         }
     }
+}
 
-    result
+/// The report formats, as [`Format`] on the command line and in the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum ReportFormat {
+    /// Only the terminal output
+    Text,
+    /// A single JSON document, written when the crawl is done
+    Json,
+    /// One JSON object per line, written as results come in
+    Jsonl,
+    /// JUnit XML, with a testcase for every checked URL
+    Junit,
 }
 
-#[derive(Debug, Default)]
-struct ResultState {
-    count: usize,
-    error_count: usize,
-    last_len: usize,
+impl From<ReportFormat> for Format {
+    fn from(format: ReportFormat) -> Format {
+        match format {
+            ReportFormat::Text => Format::Text,
+            ReportFormat::Json => Format::Json,
+            ReportFormat::Jsonl => Format::Jsonl,
+            ReportFormat::Junit => Format::Junit,
+        }
+    }
+}
+
+/// A number of seconds, like `1.5`.
+fn seconds(value: &str) -> Result<Duration, String> {
+    let seconds: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
 }
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct CmdLineArgs {
     #[arg(help = "Please provide an URL to check - like https://tweedegolf.nl/")]
-    base_url: Url,
-    #[arg(short('e'), long, num_args(1..))]
-    exclude_pattern: Option<Regex>,
+    base_url: Option<Url>,
+    /// Read options from this file, instead of from tgcheck.toml in the working directory
+    #[arg(short('c'), long)]
+    config: Option<PathBuf>,
+    /// Do not check URLs matching this regex, or this glob when prefixed with glob: (repeatable)
+    #[arg(short('e'), long, alias("exclude-pattern"))]
+    exclude: Vec<Pattern>,
+    /// Only check pages and assets on the site that match one of these patterns (repeatable)
+    #[arg(short('i'), long)]
+    include: Vec<Pattern>,
+    /// Only crawl pages under this path, like /docs/; links to other pages are still checked
+    #[arg(long)]
+    scope: Vec<String>,
+    /// Check pages matching this pattern, but not the links on them (repeatable)
+    #[arg(long)]
+    no_crawl: Vec<Pattern>,
+    /// Also crawl the subdomains of the site, like docs.example.com for example.com
+    #[arg(long)]
+    subdomains: bool,
+    /// Another name of the site, like www.example.com, crawled as part of it (repeatable)
+    #[arg(long)]
+    alias: Vec<String>,
+    /// Another host to crawl along with the site (repeatable)
+    #[arg(long)]
+    crawl_host: Vec<String>,
+    /// Treat links to the site over the other scheme (http or https) as external links
+    #[arg(long)]
+    strict_scheme: bool,
     #[arg(short('H'), long)]
     request_headers: Vec<String>,
     #[arg(short('b'), long)]
     verbose: bool,
     #[arg(default_value = "1000", short, long)]
     max_concurrent: u16,
+    /// Also check links to other hosts, without crawling them
+    #[arg(short('x'), long)]
+    check_external: bool,
+    #[arg(default_value = "16", long)]
+    max_concurrent_external: u16,
+    /// The number of requests to send to a single host at the same time
+    #[arg(default_value = "8", long)]
+    max_concurrent_per_host: usize,
+    /// The number of requests per second to send to a single host, lowered automatically when the
+    /// host responds slowly or with 429 or 503
+    #[arg(default_value = "10", long)]
+    requests_per_second: f64,
+    /// Also check every page listed in the sitemap(s) of the site
+    #[arg(short('s'), long)]
+    sitemap: bool,
+    /// Compare the sitemap(s) with the pages found by crawling, implies --sitemap
+    #[arg(long)]
+    orphans: bool,
+    /// Also crawl URLs that robots.txt disallows, and ignore its crawl delay
+    #[arg(long)]
+    ignore_robots: bool,
+    /// Treat URLs with and without a trailing slash as the same page
+    #[arg(long)]
+    fold_trailing_slash: bool,
+    /// Treat URLs with the same query parameters in a different order as the same page
+    #[arg(long)]
+    sort_query: bool,
+    /// Ignore this query parameter when recognizing duplicate URLs, a trailing * matches any suffix
+    /// (like utm_*)
+    #[arg(long)]
+    strip_param: Vec<String>,
+    /// The longest chain of redirects to follow before reporting an error
+    #[arg(default_value = "5", long)]
+    max_redirects: usize,
+    /// How often to retry connection errors, timeouts, 429 and 5xx responses
+    #[arg(default_value = "2", long)]
+    retries: u32,
+    /// The delay in seconds before the first retry, doubled for every next attempt
+    #[arg(default_value = "1", long, value_parser = seconds)]
+    retry_delay: Duration,
+    /// How many seconds to wait for the requests in flight after Ctrl-C, before giving up on them
+    #[arg(default_value = "10", long, value_parser = seconds)]
+    shutdown_timeout: Duration,
+    /// Request a URL that does not exist on every host, and fail the pages that look just like the
+    /// error page that comes back with a successful status
+    #[arg(long)]
+    soft_404: bool,
+    /// Fail the pages whose title matches this regex, like "(?i)not found" (repeatable)
+    #[arg(long)]
+    soft_404_title: Vec<Regex>,
+    /// Save progress to this file, and continue from it if it exists. It is removed when the crawl
+    /// completes.
+    #[arg(long)]
+    resume: Option<PathBuf>,
+    /// The format of the report
+    #[arg(default_value = "text", long, value_enum)]
+    format: ReportFormat,
+    /// Write the report to this file instead of stdout
+    #[arg(short('o'), long)]
+    output: Option<PathBuf>,
 }
 
 #[tokio::main]
 async fn main() {
-    let args = CmdLineArgs::parse();
-    let url = args.base_url;
+    let matches = CmdLineArgs::command().get_matches();
+    let mut args = CmdLineArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let mut config = match Config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("! {e}");
+            std::process::exit(2);
+        }
+    };
+    config.apply(&mut args, &matches);
+
+    let Some(url) = args.base_url.clone() else {
+        CmdLineArgs::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "Please provide an URL to check, on the command line or as base-url in the config",
+            )
+            .exit()
+    };
     let verbose = args.verbose;
 
     let mut header_map = HeaderMap::new();
-    for header in args.request_headers {
+    for header in &args.request_headers {
         let key_value: Vec<_> = header.split(':').collect();
         if key_value.len() != 2 {
             panic!("Please make sure to provide any headers as `<key>: <value>` pair, seperated by a colon")
@@ -230,103 +400,189 @@ async fn main() {
         header_map.append(header_name, key_value[1].trim().parse().unwrap());
     }
 
-    let client = ClientBuilder::new()
-        .connect_timeout(Duration::from_secs(15))
-        .danger_accept_invalid_certs(true)
-        .default_headers(header_map)
-        .user_agent(APP_USER_AGENT)
-        .build()
-        .unwrap();
-
-    let todo = Arc::new(AtomicUsize::new(0));
-
-    let (tx, mut rx) = mpsc::channel::<Option<(Url, Url)>>(512);
-    let (result_tx, mut result_rx) = mpsc::channel::<ResponseResult>(512);
-
-    tx.send(Some((url.clone(), url.clone()))).await.unwrap();
-
-    let output_tx = tx.clone();
-    let output_todo = todo.clone();
-
-    let handle = task::spawn(async move {
-        let start: Instant = Instant::now();
-        let mut state = ResultState::default();
+    let writer: Box<dyn Write + Send> = match &args.output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("! could not create {}: {e}", path.display());
+                std::process::exit(2);
+            }
+        },
+        None => {
+            if args.format != ReportFormat::Text {
+                REPORT_ON_STDOUT.store(true, Ordering::Relaxed);
+            }
+            Box::new(io::stdout())
+        }
+    };
+    let mut reporter = report::reporter(args.format.into(), writer);
+
+    let crawl_config = CrawlConfig::new(url.clone())
+        .request_headers(header_map)
+        .max_concurrent(args.max_concurrent as usize)
+        .check_external(args.check_external)
+        .max_concurrent_external(args.max_concurrent_external as usize)
+        .max_concurrent_per_host(args.max_concurrent_per_host)
+        .requests_per_second(args.requests_per_second)
+        .sitemap(args.sitemap)
+        .orphans(args.orphans)
+        .ignore_robots(args.ignore_robots)
+        .fold_trailing_slash(args.fold_trailing_slash)
+        .sort_query(args.sort_query)
+        .strip_params(args.strip_param)
+        .subdomains(args.subdomains)
+        .aliases(args.alias)
+        .crawl_hosts(args.crawl_host)
+        .strict_scheme(args.strict_scheme)
+        .include(args.include)
+        .exclude(args.exclude)
+        .scope(args.scope)
+        .no_crawl(args.no_crawl)
+        .max_redirects(args.max_redirects)
+        .retries(args.retries)
+        .retry_delay(args.retry_delay)
+        .shutdown_timeout(args.shutdown_timeout)
+        .rules(config.rules)
+        .soft_404(args.soft_404)
+        .soft_404_titles(args.soft_404_title)
+        .resume(args.resume.clone());
+
+    let mut crawl = match Crawler::new(crawl_config).start() {
+        Ok(crawl) => crawl,
+        Err(e) => {
+            let path = args.resume.unwrap_or_default();
+            eprintln!("! could not open {}: {e}", path.display());
+            std::process::exit(2);
+        }
+    };
 
-        tokio::time::sleep(Duration::from_secs(1)).await;
-        println!(">>> starting {}", url.host_str().unwrap_or_default());
+    info!(">>> starting {}", url.host_str().unwrap_or_default());
+
+    let mut progress = Progress::default();
+    let stop = signal();
+    tokio::pin!(stop);
+    let mut interrupted = false;
+
+    loop {
+        let event = tokio::select! {
+            event = crawl.next() => match event {
+                Some(event) => event,
+                None => break,
+            },
+            _ = &mut stop, if !interrupted => {
+                interrupted = true;
+                crawl.interrupt();
+                eprintln!("! interrupted, waiting for the requests in flight, press Ctrl-C again to quit");
+                task::spawn(async {
+                    signal().await;
+                    std::process::exit(INTERRUPTED);
+                });
+                continue;
+            }
+        };
+
+        match event {
+            Event::Fetching { url, kind } if verbose => match kind {
+                LinkKind::External => info!("> checking {url}"),
+                _ => info!("> fetching {url}"),
+            },
+            Event::Fetching { .. } => {}
+            Event::Skipped { url, reason } => match reason {
+                SkipReason::Excluded => info!("> exclude: {url}"),
+                SkipReason::Robots => info!("> robots: {url}"),
+            },
+            Event::Checked {
+                result,
+                referrers,
+                remaining,
+            } => {
+                if let Some(reporter) = &mut reporter {
+                    if let Err(e) = reporter.result(&result, &referrers) {
+                        eprintln!("! could not write report: {e}");
+                    }
+                }
+                log_result(*result, &mut progress, remaining, verbose);
+            }
+            Event::Info(message) => info!("> {message}"),
+            Event::Error(message) => eprintln!("! {message}"),
+        }
+    }
 
-        while let Some(result) = result_rx.recv().await {
-            output_todo.fetch_sub(1, Ordering::SeqCst);
-            let todo_value = output_todo.load(Ordering::SeqCst);
+    let summary = crawl.finish().await;
 
-            log_result(result, &mut state, todo_value, verbose);
+    log_broken_anchors(&summary.broken_anchors, &progress);
+    log_referrers(&summary.failed, &summary.referrers);
 
-            if todo_value == 0 {
-                break;
-            }
+    let line = format!(
+        "<<< {} {}, time elapsed: {:.1}s, total pages: {:?}, {}",
+        if summary.interrupted {
+            "interrupted"
+        } else {
+            "finished"
+        },
+        summary.host,
+        summary.duration_secs,
+        summary.pages,
+        if summary.errors > 0 {
+            format!("errors: {}", summary.errors).red()
+        } else {
+            "no errors".green()
         }
+    );
+    let line = match summary.warnings {
+        0 => line,
+        n => format!("{line}, {}", format!("warnings: {n}").yellow()),
+    };
+    let line = match summary.flaky {
+        0 => line,
+        n => format!("{line}, {}", format!("flaky: {n}").yellow()),
+    };
+    let whitespace = " ".repeat(progress.last_len.saturating_sub(line.len()));
 
-        output_tx.send(None).await.unwrap();
-        result_rx.close();
-
-        let duration = start.elapsed();
+    info!("{line}{whitespace}");
 
-        let line = format!(
-            "<<< finished {}, time elapsed: {:.1}s, total pages: {:?}, {}",
-            url.host_str().unwrap_or_default(),
-            duration.as_secs_f64(),
-            state.count,
-            if state.error_count > 0 {
-                format!("errors: {}", state.error_count).red()
-            } else {
+    if summary.external > 0 {
+        info!(
+            "<<< external links: {}, {}",
+            summary.external,
+            if summary.external_errors == 0 {
                 "no errors".green()
+            } else {
+                format!("errors: {}", summary.external_errors).red()
             }
         );
-        let whitespace = " ".repeat(state.last_len.saturating_sub(line.len()));
-
-        println!("{line}{whitespace}");
 
-        state
-    });
-
-    let mut seen = HashSet::new();
-    let sem = Arc::new(Semaphore::new(args.max_concurrent as usize));
-    let running_average_response_time = Arc::new(Mutex::new(1.));
+        for line in &progress.external_failures {
+            eprintln!("{line}");
+        }
+    }
 
-    while let Some(Some((url, from))) = rx.recv().await {
-        if let Some(exclude_pattern) = &args.exclude_pattern {
-            if exclude_pattern.is_match(url.as_str()) {
-                println!("> exclude: {url}");
-                continue;
+    if !summary.unchecked.is_empty() {
+        info!(
+            "<<< not checked: {}",
+            format!("{}", summary.unchecked.len()).yellow()
+        );
+        if verbose {
+            for url in &summary.unchecked {
+                info!("> {url}");
             }
         }
-        if !seen.contains(&url) {
-            seen.insert(url.clone());
-            todo.fetch_add(1, Ordering::SeqCst);
-
-            let inner_tx = tx.clone();
-            let inner_result_tx = result_tx.clone();
-
-            let sleep_time = 0_f64.max(*running_average_response_time.lock().await - 0.5).max(1.0);
-            sleep(Duration::from_secs_f64(sleep_time)).await;
-
-            let permit = sem.clone().acquire_owned().await.unwrap();
+    }
 
-            let client = client.clone();
-            let running_average = running_average_response_time.clone();
-            task::spawn(async move {
-                if verbose {
-                    println!("> fetching {url}");
-                }
-                let result = fetch(url, from, inner_tx, client, permit, running_average).await;
-                inner_result_tx.send(result).await.unwrap();
-            });
+    if let Some(reporter) = &mut reporter {
+        if let Err(e) = reporter.finish(&summary) {
+            eprintln!("! could not write report: {e}");
         }
     }
 
-    let state = handle.await.unwrap();
+    if let Some(orphans) = &summary.orphans {
+        log_orphans(orphans);
+    }
 
-    if state.error_count > 0 {
+    if summary.interrupted {
+        std::process::exit(INTERRUPTED);
+    }
+    if summary.errors > 0 || summary.external_errors > 0 {
         std::process::exit(1);
     }
 }
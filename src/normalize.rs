@@ -0,0 +1,250 @@
+use reqwest::Url;
+
+/// Rewrites URLs into a canonical form, so that different spellings of the same page are only
+/// checked once. Parsing a `Url` already lowercases the scheme and host, removes default ports and
+/// resolves dot segments; the rest is done here. The canonical form is only used to compare URLs,
+/// they are still requested as linked.
+#[derive(Debug, Default, Clone)]
+pub struct Normalizer {
+    /// Treat `/page/` and `/page` as the same URL.
+    pub fold_trailing_slash: bool,
+    /// Treat `?a=1&b=2` and `?b=2&a=1` as the same query.
+    pub sort_query: bool,
+    /// Query parameters to ignore, either exact names or prefixes ending in `*` like `utm_*`.
+    pub strip_params: Vec<String>,
+    /// The host of the site, which the URLs on its aliases are rewritten to.
+    pub host: String,
+    /// Other names of the site, like `www.example.com` for `example.com`.
+    pub aliases: Vec<String>,
+    /// The scheme that URLs on the site are rewritten to, unless the other scheme is another site.
+    pub scheme: Option<String>,
+}
+
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+}
+
+/// Decode percent-encoded unreserved characters and uppercase all other percent-encodings, as
+/// described in <https://www.rfc-editor.org/rfc/rfc3986#section-6.2.2>.
+fn normalize_percent_encoding(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut output = String::with_capacity(input.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+        match escape {
+            Some(byte) if is_unreserved(byte) => {
+                output.push(byte as char);
+                i += 3;
+            }
+            Some(byte) => {
+                output.push_str(&format!("%{byte:02X}"));
+                i += 3;
+            }
+            None => {
+                let c = input[i..].chars().next().unwrap();
+                output.push(c);
+                i += c.len_utf8();
+            }
+        }
+    }
+
+    output
+}
+
+impl Normalizer {
+    fn is_stripped(&self, name: &str) -> bool {
+        self.strip_params
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            })
+    }
+
+    pub fn normalize(&self, mut url: Url) -> Url {
+        url.set_fragment(None);
+
+        let on_site = url
+            .host_str()
+            .is_some_and(|host| host == self.host || self.aliases.iter().any(|a| a == host));
+        if on_site {
+            let _ = url.set_host(Some(&self.host));
+            if let Some(scheme) = &self.scheme {
+                let _ = url.set_scheme(scheme);
+            }
+        }
+
+        let mut path = normalize_percent_encoding(url.path());
+        if self.fold_trailing_slash && path.len() > 1 && path.ends_with('/') {
+            path.pop();
+        }
+        url.set_path(&path);
+
+        if let Some(query) = url.query() {
+            let mut pairs: Vec<String> = query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .filter(|pair| !self.is_stripped(pair.split('=').next().unwrap_or_default()))
+                .map(normalize_percent_encoding)
+                .collect();
+            if self.sort_query {
+                pairs.sort();
+            }
+
+            let query = pairs.join("&");
+            url.set_query((!query.is_empty()).then_some(&query));
+        }
+
+        url
+    }
+
+    /// Like [`Normalizer::normalize`], for a URL that was already turned into a string.
+    pub fn normalize_str(&self, url: &str) -> String {
+        match Url::parse(url) {
+            Ok(url) => self.normalize(url).into(),
+            Err(_) => url.to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(normalizer: &Normalizer, url: &str) -> String {
+        normalizer.normalize(Url::parse(url).unwrap()).into()
+    }
+
+    #[test]
+    fn defaults() {
+        let normalizer = Normalizer::default();
+        // The examples of RFC 3986, section 6.2.2
+        assert_eq!(
+            normalize(&normalizer, "HTTP://www.Example.com/%7ebob"),
+            "http://www.example.com/~bob"
+        );
+        assert_eq!(
+            normalize(&normalizer, "http://example.com/a/./b/../c"),
+            "http://example.com/a/c"
+        );
+        assert_eq!(
+            normalize(&normalizer, "http://example.com:80/a%2fb%3a"),
+            "http://example.com/a%2Fb%3A"
+        );
+        assert_eq!(
+            normalize(&normalizer, "https://example.com/page#section"),
+            "https://example.com/page"
+        );
+        assert_eq!(
+            normalize(&normalizer, "https://example.com/page?"),
+            "https://example.com/page"
+        );
+        assert_eq!(
+            normalize(&normalizer, "https://example.com/page?&a=1&&b=%7e"),
+            "https://example.com/page?a=1&b=~"
+        );
+        // Without options, slashes and the order of parameters are kept
+        assert_eq!(
+            normalize(&normalizer, "https://example.com/docs/?b=2&a=1"),
+            "https://example.com/docs/?b=2&a=1"
+        );
+    }
+
+    #[test]
+    fn fold_trailing_slash() {
+        let normalizer = Normalizer {
+            fold_trailing_slash: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(&normalizer, "https://example.com/docs/"),
+            "https://example.com/docs"
+        );
+        assert_eq!(
+            normalize(&normalizer, "https://example.com/"),
+            "https://example.com/"
+        );
+    }
+
+    #[test]
+    fn sort_query() {
+        let normalizer = Normalizer {
+            sort_query: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(&normalizer, "https://example.com/?y=2&x=1&x=0"),
+            "https://example.com/?x=0&x=1&y=2"
+        );
+    }
+
+    #[test]
+    fn strip_params() {
+        let normalizer = Normalizer {
+            strip_params: vec!["utm_*".to_owned(), "ref".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(
+                &normalizer,
+                "https://example.com/?utm_source=x&id=1&ref=y&referrer=z"
+            ),
+            "https://example.com/?id=1&referrer=z"
+        );
+        assert_eq!(
+            normalize(&normalizer, "https://example.com/?utm_medium=x"),
+            "https://example.com/"
+        );
+    }
+
+    #[test]
+    fn normalize_str() {
+        let normalizer = Normalizer::default();
+        assert_eq!(
+            normalizer.normalize_str("https://example.com/%7e#x"),
+            "https://example.com/~"
+        );
+        assert_eq!(normalizer.normalize_str("not a url"), "not a url");
+    }
+
+    #[test]
+    fn site() {
+        let normalizer = Normalizer {
+            host: "example.com".to_owned(),
+            aliases: vec!["www.example.com".to_owned()],
+            scheme: Some("https".to_owned()),
+            ..Default::default()
+        };
+        for url in [
+            "https://example.com/a",
+            "https://www.example.com/a",
+            "http://example.com/a",
+            "http://www.example.com:80/a",
+        ] {
+            assert_eq!(normalize(&normalizer, url), "https://example.com/a");
+        }
+        assert_eq!(
+            normalize(&normalizer, "http://docs.example.com/a"),
+            "http://docs.example.com/a"
+        );
+        assert_eq!(
+            normalize(&normalizer, "http://example.com:8080/a"),
+            "https://example.com:8080/a"
+        );
+
+        let normalizer = Normalizer {
+            scheme: None,
+            ..normalizer
+        };
+        assert_eq!(
+            normalize(&normalizer, "http://www.example.com/a"),
+            "http://example.com/a"
+        );
+    }
+}
@@ -0,0 +1,408 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    check::{Finding, Severity},
+    LinkKind, Outcome, Referrer, Referrers, ResponseResult, Summary,
+};
+
+/// The format of the report, next to the coloured progress output on the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Only the terminal output
+    Text,
+    /// A single JSON document, written when the crawl is done
+    Json,
+    /// One JSON object per line, written as results come in
+    Jsonl,
+    /// JUnit XML, with a testcase for every checked URL
+    Junit,
+}
+
+#[derive(Debug, Serialize)]
+struct RedirectEntry<'a> {
+    status: u16,
+    location: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct ResultEntry<'a> {
+    url: &'a str,
+    kind: LinkKind,
+    referrers: &'a [Referrer],
+    outcome: Outcome,
+    status: Option<u16>,
+    size: Option<usize>,
+    duration_ms: u128,
+    attempts: u32,
+    redirects: Vec<RedirectEntry<'a>>,
+    error: Option<&'a str>,
+    warnings: &'a [String],
+    findings: &'a [Finding],
+}
+
+impl<'a> ResultEntry<'a> {
+    fn new(result: &'a ResponseResult, referrers: &'a [Referrer]) -> Self {
+        ResultEntry {
+            url: &result.url,
+            kind: result.kind,
+            referrers,
+            outcome: result.outcome(),
+            status: result.status.map(|status| status.as_u16()),
+            size: result.size,
+            duration_ms: result.duration.as_millis(),
+            attempts: result.attempts,
+            redirects: result
+                .redirects
+                .iter()
+                .map(|redirect| RedirectEntry {
+                    status: redirect.status.as_u16(),
+                    location: &redirect.location,
+                })
+                .collect(),
+            error: result.error.as_deref(),
+            warnings: &result.warnings,
+            findings: &result.findings,
+        }
+    }
+}
+
+/// Receives every result as it comes in, with the pages known to link to it so far, and the
+/// summary, which has the complete referrer index, at the end.
+pub trait Reporter: Send {
+    fn result(&mut self, result: &ResponseResult, referrers: &[Referrer]) -> io::Result<()>;
+    fn finish(&mut self, summary: &Summary) -> io::Result<()>;
+}
+
+struct Json {
+    writer: Box<dyn Write + Send>,
+    results: Vec<serde_json::Value>,
+}
+
+impl Reporter for Json {
+    fn result(&mut self, result: &ResponseResult, referrers: &[Referrer]) -> io::Result<()> {
+        self.results
+            .push(serde_json::to_value(ResultEntry::new(result, referrers))?);
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &Summary) -> io::Result<()> {
+        // Pages found later in the crawl may link to results that were already in
+        for entry in &mut self.results {
+            let all = entry["url"]
+                .as_str()
+                .and_then(|url| summary.referrers.get(url));
+            if let Some(all) = all {
+                entry["referrers"] = serde_json::to_value(all)?;
+            }
+        }
+
+        let report = serde_json::json!({
+            "results": self.results,
+            "summary": summary,
+        });
+        serde_json::to_writer_pretty(&mut self.writer, &report)?;
+        writeln!(self.writer)?;
+        self.writer.flush()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Line<'a> {
+    Result(ResultEntry<'a>),
+    Summary(&'a Summary),
+}
+
+struct JsonLines {
+    writer: Box<dyn Write + Send>,
+}
+
+impl JsonLines {
+    fn write(&mut self, line: Line) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, &line)?;
+        writeln!(self.writer)?;
+        self.writer.flush()
+    }
+}
+
+impl Reporter for JsonLines {
+    fn result(&mut self, result: &ResponseResult, referrers: &[Referrer]) -> io::Result<()> {
+        self.write(Line::Result(ResultEntry::new(result, referrers)))
+    }
+
+    fn finish(&mut self, summary: &Summary) -> io::Result<()> {
+        self.write(Line::Summary(summary))
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than whitespace are not allowed in XML 1.0 at all
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Pages and assets are grouped by their host and first directory, external links by host.
+fn suite_name(url: &str, kind: LinkKind) -> String {
+    let Ok(url) = Url::parse(url) else {
+        return url.to_owned();
+    };
+    let host = url.host_str().unwrap_or_default();
+
+    match (kind, url.path().trim_start_matches('/').split_once('/')) {
+        (LinkKind::External, _) | (_, None) => host.to_owned(),
+        (_, Some((directory, _))) => format!("{host}/{directory}"),
+    }
+}
+
+#[derive(Debug)]
+struct Failure {
+    message: String,
+    kind: String,
+    details: String,
+}
+
+#[derive(Debug)]
+struct Case {
+    name: String,
+    time: f64,
+    failure: Option<Failure>,
+    /// Not checked at all, because the crawl was interrupted.
+    skipped: bool,
+    warnings: Vec<String>,
+    /// The checked URL, whose referrers are only all known at the end.
+    url: Option<String>,
+}
+
+impl Case {
+    fn write(&self, writer: &mut dyn Write, suite: &str, referrers: &Referrers) -> io::Result<()> {
+        write!(
+            writer,
+            r#"    <testcase name="{}" classname="{}" time="{:.3}""#,
+            escape(&self.name),
+            escape(suite),
+            self.time
+        )?;
+        if self.failure.is_none() && !self.skipped && self.warnings.is_empty() {
+            return writeln!(writer, "/>");
+        }
+        writeln!(writer, ">")?;
+
+        if self.skipped {
+            writeln!(
+                writer,
+                r#"      <skipped message="not checked, the crawl was interrupted"/>"#
+            )?;
+        }
+
+        if let Some(failure) = &self.failure {
+            let mut details = failure.details.clone();
+            let referrers = self.url.as_ref().and_then(|url| referrers.get(url));
+            for referrer in referrers.into_iter().flatten() {
+                details.push_str(&format!("linked from: {}", referrer.page));
+                if let Some(line) = referrer.line {
+                    details.push_str(&format!(":{line}"));
+                }
+                if !referrer.text.is_empty() {
+                    details.push_str(&format!(" \"{}\"", referrer.text));
+                }
+                details.push('\n');
+            }
+
+            writeln!(
+                writer,
+                r#"      <failure message="{}" type="{}">{}</failure>"#,
+                escape(&failure.message),
+                escape(&failure.kind),
+                escape(&details)
+            )?;
+        }
+        if !self.warnings.is_empty() {
+            writeln!(
+                writer,
+                "      <system-out>{}</system-out>",
+                escape(&self.warnings.join("\n"))
+            )?;
+        }
+        writeln!(writer, "    </testcase>")
+    }
+}
+
+#[derive(Debug, Default)]
+struct Suite {
+    cases: Vec<Case>,
+    failures: usize,
+    skipped: usize,
+    time: f64,
+}
+
+struct JUnit {
+    writer: Box<dyn Write + Send>,
+    suites: BTreeMap<String, Suite>,
+}
+
+impl JUnit {
+    fn add(&mut self, suite: String, case: Case) {
+        let suite = self.suites.entry(suite).or_default();
+        suite.time += case.time;
+        if case.failure.is_some() {
+            suite.failures += 1;
+        }
+        if case.skipped {
+            suite.skipped += 1;
+        }
+        suite.cases.push(case);
+    }
+}
+
+impl Reporter for JUnit {
+    fn result(&mut self, result: &ResponseResult, _referrers: &[Referrer]) -> io::Result<()> {
+        let failure = (result.outcome() == Outcome::Error).then(|| {
+            let status = match result.status {
+                Some(status) => status.to_string(),
+                None => "ERROR".to_owned(),
+            };
+            let message = match &result.error {
+                Some(error) => error.clone(),
+                None => result
+                    .findings
+                    .iter()
+                    .find(|finding| finding.severity == Severity::Error)
+                    .map(|finding| finding.message.clone())
+                    .unwrap_or_default(),
+            };
+            let mut details = format!("{} {}\nstatus: {status}\n", result.kind, result.url);
+            if let Some(size) = result.size {
+                details.push_str(&format!("size: {size} bytes\n"));
+            }
+            for redirect in &result.redirects {
+                details.push_str(&format!(
+                    "redirect: {} {}\n",
+                    redirect.status, redirect.location
+                ));
+            }
+            for finding in &result.findings {
+                details.push_str(&format!("{} check: {}\n", finding.check, finding.message));
+            }
+
+            Failure {
+                message,
+                kind: result.kind.to_string(),
+                details,
+            }
+        });
+
+        let case = Case {
+            name: result.url.clone(),
+            time: result.duration.as_secs_f64(),
+            failure,
+            skipped: false,
+            warnings: result
+                .warnings
+                .iter()
+                .cloned()
+                .chain(
+                    result
+                        .findings
+                        .iter()
+                        .filter(|finding| finding.severity == Severity::Warning)
+                        .map(|finding| format!("{}: {}", finding.check, finding.message)),
+                )
+                .collect(),
+            url: Some(result.url.clone()),
+        };
+        self.add(suite_name(&result.url, result.kind), case);
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &Summary) -> io::Result<()> {
+        for link in &summary.broken_anchors {
+            let case = Case {
+                name: format!("{}#{}", link.target, link.fragment),
+                time: 0.,
+                failure: Some(Failure {
+                    message: "broken anchor".to_owned(),
+                    kind: "anchor".to_owned(),
+                    details: format!("linked from: {}:{}\n", link.from, link.line),
+                }),
+                skipped: false,
+                warnings: Vec::new(),
+                url: None,
+            };
+            self.add(suite_name(&link.target, LinkKind::Page), case);
+        }
+        for url in &summary.unchecked {
+            let case = Case {
+                name: url.clone(),
+                time: 0.,
+                failure: None,
+                skipped: true,
+                warnings: Vec::new(),
+                url: None,
+            };
+            self.add(suite_name(url, LinkKind::Page), case);
+        }
+
+        let tests: usize = self.suites.values().map(|suite| suite.cases.len()).sum();
+        let failures: usize = self.suites.values().map(|suite| suite.failures).sum();
+        let skipped: usize = self.suites.values().map(|suite| suite.skipped).sum();
+
+        writeln!(self.writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            self.writer,
+            r#"<testsuites name="tgcheck {}" tests="{tests}" failures="{failures}" skipped="{skipped}" time="{:.3}">"#,
+            escape(&summary.host),
+            summary.duration_secs
+        )?;
+        for (name, suite) in &self.suites {
+            writeln!(
+                self.writer,
+                r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+                escape(name),
+                suite.cases.len(),
+                suite.failures,
+                suite.skipped,
+                suite.time
+            )?;
+            for case in &suite.cases {
+                case.write(&mut self.writer, name, &summary.referrers)?;
+            }
+            writeln!(self.writer, "  </testsuite>")?;
+        }
+        writeln!(self.writer, "</testsuites>")?;
+        self.writer.flush()
+    }
+}
+
+/// The reporter for `format`, if it produces anything besides the terminal output.
+pub fn reporter(format: Format, writer: Box<dyn Write + Send>) -> Option<Box<dyn Reporter>> {
+    match format {
+        Format::Text => None,
+        Format::Json => Some(Box::new(Json {
+            writer,
+            results: Vec::new(),
+        })),
+        Format::Jsonl => Some(Box::new(JsonLines { writer })),
+        Format::Junit => Some(Box::new(JUnit {
+            writer,
+            suites: BTreeMap::new(),
+        })),
+    }
+}
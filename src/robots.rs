@@ -0,0 +1,295 @@
+use std::time::Duration;
+
+use reqwest::{Client, Url};
+
+/// The longest crawl delay we are willing to honour, in seconds. Anything longer would keep the
+/// crawl from ever finishing.
+const MAX_CRAWL_DELAY: f64 = 60.;
+
+#[derive(Debug, Clone)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+impl Rule {
+    /// Match a robots.txt path pattern, where `*` matches any sequence of characters and a trailing
+    /// `$` anchors the pattern to the end of the path.
+    fn matches(&self, path: &str) -> bool {
+        let (pattern, anchored) = match self.pattern.strip_suffix('$') {
+            Some(pattern) => (pattern, true),
+            None => (self.pattern.as_str(), false),
+        };
+
+        let mut parts = pattern.split('*');
+        let Some(mut rest) = parts.next().and_then(|first| path.strip_prefix(first)) else {
+            return false;
+        };
+
+        let parts: Vec<&str> = parts.collect();
+        for (i, part) in parts.iter().enumerate() {
+            if anchored && i == parts.len() - 1 {
+                return rest.ends_with(part);
+            }
+            match rest.find(part) {
+                Some(idx) => rest = &rest[idx + part.len()..],
+                None => return false,
+            }
+        }
+
+        !anchored || rest.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<f64>,
+}
+
+/// The rules that apply to us for a single host.
+#[derive(Debug, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+    pub crawl_delay: Option<Duration>,
+}
+
+impl Policy {
+    /// The most specific (longest) matching rule wins, and `Allow` wins from an equally long
+    /// `Disallow`, see <https://www.rfc-editor.org/rfc/rfc9309#section-2.2.2>.
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        let path = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_owned(),
+        };
+
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(&path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .is_none_or(|rule| rule.allow)
+    }
+}
+
+/// A parsed robots.txt file.
+#[derive(Debug, Default)]
+pub struct Robots {
+    groups: Vec<Group>,
+    pub sitemaps: Vec<String>,
+}
+
+impl Robots {
+    pub fn parse(text: &str) -> Robots {
+        let mut robots = Robots::default();
+        // Consecutive user-agent lines share one group; any other line closes the list of agents
+        let mut collecting_agents = false;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim();
+
+            if key == "sitemap" {
+                robots.sitemaps.push(value.to_owned());
+                continue;
+            }
+
+            if key == "user-agent" {
+                if !collecting_agents {
+                    robots.groups.push(Group::default());
+                }
+                collecting_agents = true;
+                let group = robots.groups.last_mut().unwrap();
+                group.agents.push(value.to_ascii_lowercase());
+                continue;
+            }
+
+            collecting_agents = false;
+            let Some(group) = robots.groups.last_mut() else {
+                continue;
+            };
+
+            match key.as_str() {
+                // An empty disallow allows everything, which is the default anyway
+                "allow" | "disallow" if !value.is_empty() => group.rules.push(Rule {
+                    allow: key == "allow",
+                    pattern: value.to_owned(),
+                }),
+                "crawl-delay" => {
+                    group.crawl_delay = value
+                        .parse()
+                        .ok()
+                        .filter(|d: &f64| d.is_finite() && *d >= 0.)
+                }
+                _ => {}
+            }
+        }
+
+        robots
+    }
+
+    /// Merge all groups that name our user agent, or the `*` groups if there are none.
+    pub fn policy(&self, agent: &str) -> Policy {
+        let agent = agent.to_ascii_lowercase();
+        let named = |name: &str| {
+            self.groups
+                .iter()
+                .filter(|group| group.agents.iter().any(|a| a == name))
+                .collect::<Vec<_>>()
+        };
+
+        let mut groups = named(&agent);
+        if groups.is_empty() {
+            groups = named("*");
+        }
+
+        Policy {
+            rules: groups.iter().flat_map(|g| g.rules.clone()).collect(),
+            crawl_delay: groups
+                .iter()
+                .filter_map(|g| g.crawl_delay)
+                .reduce(f64::max)
+                .map(|delay| Duration::from_secs_f64(delay.min(MAX_CRAWL_DELAY))),
+        }
+    }
+}
+
+/// Fetch the robots.txt of the host of `base`. A missing or unreadable file means there are no
+/// restrictions.
+pub async fn fetch(client: &Client, base: &Url) -> Robots {
+    let Ok(url) = base.join("/robots.txt") else {
+        return Robots::default();
+    };
+
+    match client.get(url).send().await {
+        Ok(response) if response.status().is_success() => {
+            Robots::parse(&response.text().await.unwrap_or_default())
+        }
+        _ => Robots::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(robots: &str, path: &str) -> bool {
+        let url = Url::parse("https://example.com/")
+            .unwrap()
+            .join(path)
+            .unwrap();
+        Robots::parse(robots).policy("tgcheck").is_allowed(&url)
+    }
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        let rule = Rule {
+            allow: false,
+            pattern: pattern.to_owned(),
+        };
+        rule.matches(path)
+    }
+
+    #[test]
+    fn prefix() {
+        assert!(matches("/fish", "/fish"));
+        assert!(matches("/fish", "/fish.html"));
+        assert!(matches("/fish", "/fishheads/yummy.html"));
+        assert!(!matches("/fish", "/Fish.asp"));
+        assert!(!matches("/fish", "/catfish"));
+        assert!(matches("/fish/", "/fish/salmon.htm"));
+        assert!(!matches("/fish/", "/fish"));
+    }
+
+    #[test]
+    fn wildcard() {
+        assert!(matches("/fish*", "/fish"));
+        assert!(matches("/fish*", "/fishheads"));
+        assert!(matches("/*.php", "/index.php"));
+        assert!(matches("/*.php", "/folder/filename.php?parameters"));
+        assert!(!matches("/*.php", "/windows.PHP"));
+        assert!(matches("/fish*.php", "/fishheads/catfish.php?parameters"));
+        assert!(!matches("/fish*.php", "/Fish.PHP"));
+        assert!(matches(
+            "/path/file-with-a-*.html",
+            "/path/file-with-a-wildcard.html"
+        ));
+    }
+
+    #[test]
+    fn end_anchor() {
+        assert!(matches("/*.php$", "/filename.php"));
+        assert!(matches("/*.php$", "/folder/filename.php"));
+        assert!(!matches("/*.php$", "/filename.php?parameters"));
+        assert!(!matches("/*.php$", "/filename.php/"));
+        assert!(!matches("/*.php$", "/filename.php5"));
+        assert!(matches("/$", "/"));
+        assert!(!matches("/$", "/page"));
+        assert!(matches("/docs/a.html$", "/docs/a.html"));
+        assert!(!matches("/docs/a.html$", "/docs/a.html.bak"));
+    }
+
+    #[test]
+    fn longest_match() {
+        let robots = "User-agent: *\nAllow: /p\nDisallow: /\n";
+        assert!(allowed(robots, "/page"));
+        assert!(!allowed(robots, "/other"));
+
+        // The RFC 9309 example: the more specific allow wins
+        let robots = "User-agent: *\nDisallow: /example/\nAllow: /example/page/\n";
+        assert!(allowed(robots, "/example/page/"));
+        assert!(!allowed(robots, "/example/other.html"));
+        assert!(allowed(robots, "/elsewhere"));
+
+        let robots = "User-agent: *\nAllow: /page\nDisallow: /*.htm\n";
+        assert!(!allowed(robots, "/page.htm"));
+        assert!(allowed(robots, "/page"));
+    }
+
+    #[test]
+    fn allow_wins_ties() {
+        let robots = "User-agent: *\nDisallow: /folder\nAllow: /folder\n";
+        assert!(allowed(robots, "/folder/page"));
+    }
+
+    #[test]
+    fn query() {
+        let robots = "User-agent: *\nDisallow: /*?sort=\n";
+        assert!(!allowed(robots, "/list?sort=name"));
+        assert!(allowed(robots, "/list"));
+    }
+
+    #[test]
+    fn groups() {
+        let robots = "User-agent: googlebot\nDisallow: /\n\nUser-agent: *\nDisallow: /private/\n";
+        assert!(allowed(robots, "/public"));
+        assert!(!allowed(robots, "/private/page"));
+
+        let robots = "User-agent: TGCheck\nUser-agent: other\nDisallow: /mine/\n\nUser-agent: *\nDisallow: /\n";
+        assert!(allowed(robots, "/public"));
+        assert!(!allowed(robots, "/mine/page"));
+
+        // An empty disallow allows everything
+        assert!(allowed("User-agent: *\nDisallow:\n", "/anything"));
+    }
+
+    #[test]
+    fn crawl_delay_and_sitemaps() {
+        let robots = Robots::parse(
+            "Sitemap: https://example.com/sitemap.xml\nUser-agent: *\nCrawl-delay: 1.5 # seconds\n",
+        );
+        assert_eq!(robots.sitemaps, ["https://example.com/sitemap.xml"]);
+        let policy = robots.policy("tgcheck");
+        assert_eq!(policy.crawl_delay, Some(Duration::from_millis(1500)));
+
+        let policy = Robots::parse("User-agent: *\nCrawl-delay: 1e20\n").policy("tgcheck");
+        assert_eq!(policy.crawl_delay, Some(Duration::from_secs(60)));
+        let policy = Robots::parse("User-agent: *\nCrawl-delay: -1\n").policy("tgcheck");
+        assert_eq!(policy.crawl_delay, None);
+        let policy = Robots::parse("User-agent: *\nCrawl-delay: inf\n").policy("tgcheck");
+        assert_eq!(policy.crawl_delay, None);
+    }
+}
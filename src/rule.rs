@@ -0,0 +1,87 @@
+use regex::Regex;
+use reqwest::Url;
+use serde::{Deserialize, Deserializer};
+
+fn regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Regex>, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Deserialize a list of regexes, like the `must-contain` patterns of a rule.
+pub fn regexes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Regex>, D::Error> {
+    let patterns = Vec::<String>::deserialize(deserializer)?;
+    patterns
+        .iter()
+        .map(|pattern| Regex::new(pattern))
+        .collect::<Result<_, _>>()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Whether a `Content-Type` header value is of the media type `expected`, like `image/svg+xml`, or
+/// of any subtype when `expected` is like `image/*`.
+fn is_media_type(content_type: &str, expected: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    match expected.strip_suffix("/*") {
+        Some(expected) => media_type
+            .split('/')
+            .next()
+            .is_some_and(|kind| kind.eq_ignore_ascii_case(expected)),
+        None => media_type.eq_ignore_ascii_case(expected),
+    }
+}
+
+/// Settings for the URLs under a path prefix, or matching a pattern, or of a content type.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Rule {
+    /// A path like `/downloads/`, or the start of a full URL for other hosts.
+    pub prefix: Option<String>,
+    #[serde(deserialize_with = "regex")]
+    pub pattern: Option<Regex>,
+    /// A media type like `application/json`, or `image/*` for all images. Rules with a content
+    /// type do not apply before the response is in, so they cannot exclude URLs.
+    pub content_type: Option<String>,
+    /// Status codes that are not errors for these URLs.
+    pub ignore_status: Vec<u16>,
+    /// The smallest acceptable response body, in bytes.
+    pub min_size: Option<usize>,
+    /// The largest acceptable response body, in bytes.
+    pub max_size: Option<usize>,
+    /// Patterns that the response body should match, like a price element on product pages.
+    #[serde(deserialize_with = "regexes")]
+    pub must_contain: Vec<Regex>,
+    /// Patterns that the response body should not match, like `Lorem ipsum` or `\{\{.*\}\}`.
+    #[serde(deserialize_with = "regexes")]
+    pub must_not_contain: Vec<Regex>,
+    /// Do not check these URLs at all.
+    pub exclude: bool,
+}
+
+impl Rule {
+    /// Whether the rule looks at the response body, which then has to be read as text.
+    pub fn checks_content(&self) -> bool {
+        !self.must_contain.is_empty() || !self.must_not_contain.is_empty()
+    }
+
+    /// Whether the rule applies to `url`, served as `content_type` if the response is in.
+    pub fn matches(&self, url: &Url, content_type: Option<&str>) -> bool {
+        let prefix = self
+            .prefix
+            .as_deref()
+            .is_none_or(|prefix| match prefix.starts_with('/') {
+                true => url.path().starts_with(prefix),
+                false => url.as_str().starts_with(prefix),
+            });
+        let pattern = self
+            .pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.is_match(url.as_str()));
+        let content_type = self.content_type.as_deref().is_none_or(|expected| {
+            content_type.is_some_and(|content_type| is_media_type(content_type, expected))
+        });
+
+        prefix && pattern && content_type
+    }
+}
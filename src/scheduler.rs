@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use reqwest::{StatusCode, Url};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{sleep, Instant},
+};
+
+/// We never slow down further than one request per this many seconds.
+const MIN_RATE: f64 = 1. / 30.;
+
+/// How much slower than the fastest we have seen a host may become before we back off.
+const LATENCY_TOLERANCE: f64 = 2.;
+
+/// The limits that apply to every host, unless robots.txt asks for less.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub requests_per_second: f64,
+    pub max_concurrent: usize,
+}
+
+/// A token bucket whose rate adapts to how the host is coping.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    capacity: f64,
+    rate: f64,
+    max_rate: f64,
+    last: Instant,
+    /// Moving average of the response time in seconds, and the lowest it has been.
+    latency: Option<f64>,
+    best_latency: f64,
+}
+
+impl Bucket {
+    fn new(rate: f64) -> Bucket {
+        let capacity = rate.max(1.);
+
+        Bucket {
+            tokens: capacity,
+            capacity,
+            rate,
+            max_rate: rate,
+            last: Instant::now(),
+            latency: None,
+            best_latency: f64::INFINITY,
+        }
+    }
+
+    /// Take a token, or return how long to wait until one is available.
+    fn take(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1. - self.tokens) / self.rate))
+        }
+    }
+
+    fn report(&mut self, status: Option<StatusCode>, latency: Duration) {
+        let latency = latency.as_secs_f64();
+        let average = match self.latency {
+            Some(average) => average * 0.9 + latency * 0.1,
+            None => latency,
+        };
+        self.latency = Some(average);
+        self.best_latency = self.best_latency.min(average);
+
+        let overloaded = matches!(
+            status,
+            Some(StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE)
+        );
+
+        if overloaded {
+            self.rate = (self.rate / 2.).max(MIN_RATE);
+            self.tokens = self.tokens.min(0.);
+        } else if average > self.best_latency * LATENCY_TOLERANCE {
+            self.rate = (self.rate * 0.9).max(MIN_RATE);
+        } else {
+            self.rate = (self.rate * 1.05).min(self.max_rate);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Host {
+    semaphore: Arc<Semaphore>,
+    bucket: Mutex<Bucket>,
+}
+
+impl Host {
+    async fn throttle(&self) {
+        loop {
+            let wait = self.bucket.lock().unwrap().take();
+            match wait {
+                Some(wait) => sleep(wait).await,
+                None => return,
+            }
+        }
+    }
+}
+
+/// Permission to send a request to a host. Dropping it frees up the concurrency slot.
+#[derive(Debug)]
+pub struct HostPermit {
+    host: Arc<Host>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl HostPermit {
+    /// Let the scheduler know how a request went, so it can slow down when the host struggles.
+    pub fn report(&self, status: Option<StatusCode>, latency: Duration) {
+        self.host.bucket.lock().unwrap().report(status, latency);
+    }
+
+    /// Wait until another request, like a retry, may be sent to this host.
+    pub async fn throttle(&self) {
+        self.host.throttle().await;
+    }
+}
+
+/// Keeps the requests to each host within its limits, independent of the other hosts.
+#[derive(Debug)]
+pub struct Scheduler {
+    limits: Limits,
+    crawl_delays: Mutex<HashMap<String, Duration>>,
+    hosts: Mutex<HashMap<String, Arc<Host>>>,
+}
+
+fn host_key(url: &Url) -> String {
+    format!(
+        "{}:{}",
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default()
+    )
+}
+
+impl Scheduler {
+    pub fn new(limits: Limits) -> Scheduler {
+        Scheduler {
+            limits,
+            crawl_delays: Mutex::new(HashMap::new()),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A crawl delay means one request at a time, with at least the delay in between. It has to be
+    /// set before the first request to the host.
+    pub fn set_crawl_delay(&self, url: &Url, delay: Duration) {
+        self.crawl_delays
+            .lock()
+            .unwrap()
+            .insert(host_key(url), delay);
+    }
+
+    fn host(&self, url: &Url) -> Arc<Host> {
+        let key = host_key(url);
+        let mut hosts = self.hosts.lock().unwrap();
+
+        hosts
+            .entry(key)
+            .or_insert_with_key(|key| {
+                let crawl_delay = self.crawl_delays.lock().unwrap().get(key).copied();
+                let (rate, max_concurrent) = match crawl_delay {
+                    Some(delay) if !delay.is_zero() => (
+                        self.limits
+                            .requests_per_second
+                            .min(1. / delay.as_secs_f64()),
+                        1,
+                    ),
+                    _ => (self.limits.requests_per_second, self.limits.max_concurrent),
+                };
+
+                Arc::new(Host {
+                    semaphore: Arc::new(Semaphore::new(max_concurrent.max(1))),
+                    bucket: Mutex::new(Bucket::new(rate.max(MIN_RATE))),
+                })
+            })
+            .clone()
+    }
+
+    /// Wait for a free slot and a token for the host of `url`.
+    pub async fn acquire(&self, url: &Url) -> HostPermit {
+        let host = self.host(url);
+        let permit = host.semaphore.clone().acquire_owned().await.unwrap();
+        host.throttle().await;
+
+        HostPermit {
+            host,
+            _permit: permit,
+        }
+    }
+}
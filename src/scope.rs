@@ -0,0 +1,232 @@
+use std::str::FromStr;
+
+use regex::Regex;
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::LinkKind;
+
+/// A URL pattern: a regex matched anywhere in the full URL, or a glob prefixed with `glob:`. A glob
+/// starting with `/` is matched against the path (and query) only, other globs against the full
+/// URL. In globs `*` matches anything but a `/`, `**` matches anything and `?` a single character.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Pattern {
+    regex: Regex,
+    path_only: bool,
+}
+
+/// Translate a glob into an anchored regex.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    regex.push('$');
+    regex
+}
+
+impl FromStr for Pattern {
+    type Err = regex::Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let (regex, path_only) = match source.strip_prefix("glob:") {
+            Some(glob) => (Regex::new(&glob_to_regex(glob))?, glob.starts_with('/')),
+            None => (
+                Regex::new(source.strip_prefix("regex:").unwrap_or(source))?,
+                false,
+            ),
+        };
+
+        Ok(Pattern { regex, path_only })
+    }
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = regex::Error;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        source.parse()
+    }
+}
+
+impl Pattern {
+    pub fn is_match(&self, url: &Url) -> bool {
+        if !self.path_only {
+            return self.regex.is_match(url.as_str());
+        }
+
+        match url.query() {
+            Some(query) => self.regex.is_match(&format!("{}?{query}", url.path())),
+            None => self.regex.is_match(url.path()),
+        }
+    }
+}
+
+/// What happens to a URL found while crawling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Check the URL, and check the links on it if it is a page.
+    Crawl,
+    /// Check the URL, but not the links on it.
+    Check,
+    /// Do not check the URL at all.
+    Skip,
+}
+
+/// Which URLs are checked, and which pages are crawled into.
+#[derive(Debug, Default)]
+pub struct Scope {
+    /// The host and scheme of the start URL.
+    pub host: String,
+    pub scheme: String,
+    /// Other names of the site, like `www.example.com` for `example.com`.
+    pub aliases: Vec<String>,
+    /// Also crawl the subdomains of the site and its aliases.
+    pub subdomains: bool,
+    /// Other hosts to crawl along with the site.
+    pub hosts: Vec<String>,
+    /// Treat the other scheme (http or https) of the site as another site.
+    pub strict_scheme: bool,
+    /// When not empty, only pages and assets on the site that match one of these are checked.
+    pub include: Vec<Pattern>,
+    /// URLs that are not checked at all, also on other hosts.
+    pub exclude: Vec<Pattern>,
+    /// When not empty, only pages under one of these paths are crawled. Links to pages outside are
+    /// still checked.
+    pub prefixes: Vec<String>,
+    /// Pages that are checked, but whose links are not.
+    pub no_crawl: Vec<Pattern>,
+}
+
+impl Scope {
+    /// Whether `url` is part of the site, and should be crawled rather than only checked.
+    pub fn is_internal(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        if self.strict_scheme && url.scheme() != self.scheme {
+            return false;
+        }
+
+        let is_subdomain = |site: &str| {
+            // The subdomains of www.example.com are those of example.com
+            let site = site.strip_prefix("www.").unwrap_or(site);
+            host.strip_suffix(site)
+                .is_some_and(|subdomain| subdomain.is_empty() || subdomain.ends_with('.'))
+        };
+
+        std::iter::once(&self.host)
+            .chain(&self.aliases)
+            .any(|site| host == site || (self.subdomains && is_subdomain(site)))
+            || self.hosts.iter().any(|site| host == site)
+    }
+
+    pub fn decide(&self, url: &Url, kind: LinkKind) -> Decision {
+        let included = kind == LinkKind::External
+            || self.include.is_empty()
+            || self.include.iter().any(|pattern| pattern.is_match(url));
+        if !included || self.exclude.iter().any(|pattern| pattern.is_match(url)) {
+            return Decision::Skip;
+        }
+
+        let in_prefix = self.prefixes.is_empty()
+            || self
+                .prefixes
+                .iter()
+                .any(|prefix| url.path().starts_with(prefix));
+        if kind == LinkKind::Page
+            && in_prefix
+            && !self.no_crawl.iter().any(|pattern| pattern.is_match(url))
+        {
+            Decision::Crawl
+        } else {
+            Decision::Check
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, url: &str) -> bool {
+        let pattern: Pattern = pattern.parse().unwrap();
+        pattern.is_match(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn glob_to_regex_escapes() {
+        assert_eq!(glob_to_regex("/a.html"), r"^/a\.html$");
+        assert_eq!(glob_to_regex("/*/x?"), r"^/[^/]*/x[^/]$");
+        assert_eq!(glob_to_regex("/**"), "^/.*$");
+        assert_eq!(glob_to_regex("/(a)+[b]"), r"^/\(a\)\+\[b\]$");
+    }
+
+    #[test]
+    fn path_globs() {
+        assert!(matches("glob:/docs/*", "https://example.com/docs/a.html"));
+        assert!(!matches(
+            "glob:/docs/*",
+            "https://example.com/docs/a/b.html"
+        ));
+        assert!(matches(
+            "glob:/docs/**",
+            "https://example.com/docs/a/b.html"
+        ));
+        assert!(!matches(
+            "glob:/docs/*",
+            "https://example.com/other/docs/a.html"
+        ));
+        assert!(matches(
+            "glob:/page?.html",
+            "https://example.com/page1.html"
+        ));
+        assert!(!matches(
+            "glob:/page?.html",
+            "https://example.com/page10.html"
+        ));
+        assert!(matches("glob:/search?*", "https://example.com/search?q=x"));
+        assert!(matches("glob:/*.pdf", "https://example.com/file.pdf"));
+        assert!(!matches("glob:/*.pdf", "https://example.com/filexpdf"));
+    }
+
+    #[test]
+    fn url_globs() {
+        assert!(matches(
+            "glob:https://cdn.example.com/**",
+            "https://cdn.example.com/a/b.js"
+        ));
+        assert!(!matches(
+            "glob:https://cdn.example.com/**",
+            "https://example.com/a/b.js"
+        ));
+        assert!(matches(
+            "glob:https://*.example.com/**",
+            "https://docs.example.com/a"
+        ));
+    }
+
+    #[test]
+    fn regexes() {
+        assert!(matches(r"\.pdf$", "https://example.com/docs/file.pdf"));
+        assert!(matches("regex:/docs/", "https://example.com/docs/file.pdf"));
+        assert!(!matches(
+            "regex:^/docs/",
+            "https://example.com/docs/file.pdf"
+        ));
+        assert!("glob:/[".parse::<Pattern>().is_ok());
+        assert!("(".parse::<Pattern>().is_err());
+    }
+}
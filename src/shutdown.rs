@@ -0,0 +1,69 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use tokio::sync::Notify;
+
+/// Bookkeeping for stopping a crawl early, shared between the crawl handle, the dispatch loop, the
+/// fetch tasks and the output task.
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    interrupted: AtomicBool,
+    /// Dispatched URLs that have no result yet.
+    in_flight: Mutex<HashSet<String>>,
+    /// URLs that were found, but will not be checked anymore.
+    unchecked: Mutex<Vec<String>>,
+    /// Wakes up the dispatch loop when the crawl is interrupted.
+    interrupt: Notify,
+    /// Tells the output task to stop waiting for the requests that are still in flight.
+    stop: Notify,
+}
+
+impl Shutdown {
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+        self.interrupt.notify_one();
+    }
+
+    pub async fn interrupted(&self) {
+        self.interrupt.notified().await;
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::SeqCst)
+    }
+
+    pub fn dispatched(&self, url: &str) {
+        self.in_flight.lock().unwrap().insert(url.to_owned());
+    }
+
+    pub fn done(&self, url: &str) {
+        self.in_flight.lock().unwrap().remove(url);
+    }
+
+    pub fn unchecked(&self, url: &str) {
+        self.unchecked.lock().unwrap().push(url.to_owned());
+    }
+
+    pub fn stop(&self) {
+        self.stop.notify_one();
+    }
+
+    pub async fn stopped(&self) {
+        self.stop.notified().await;
+    }
+
+    /// Everything that was not checked: the URLs that were still queued, and those that were still
+    /// in flight when we stopped waiting for them.
+    pub fn take_unchecked(&self) -> Vec<String> {
+        let mut unchecked = std::mem::take(&mut *self.unchecked.lock().unwrap());
+        unchecked.extend(self.in_flight.lock().unwrap().drain());
+        unchecked.sort_unstable();
+        unchecked.dedup();
+        unchecked
+    }
+}
@@ -0,0 +1,119 @@
+use std::{collections::HashSet, io::Read};
+
+use flate2::read::GzDecoder;
+use reqwest::{Client, Url};
+use tokio::sync::mpsc::Sender;
+
+use crate::{scope::Scope, Event};
+
+/// The two kinds of documents described by <https://www.sitemaps.org/protocol.html>.
+#[derive(Debug)]
+enum Sitemap {
+    Index(Vec<String>),
+    UrlSet(Vec<String>),
+}
+
+fn parse(text: &str) -> Result<Sitemap, roxmltree::Error> {
+    let document = roxmltree::Document::parse(text)?;
+    let root = document.root_element();
+
+    let locs = root
+        .children()
+        .filter(|node| node.is_element())
+        .filter_map(|entry| {
+            entry
+                .children()
+                .find(|node| node.tag_name().name() == "loc")
+                .and_then(|loc| loc.text())
+                .map(|loc| loc.trim().to_owned())
+        })
+        .collect();
+
+    Ok(match root.tag_name().name() {
+        "sitemapindex" => Sitemap::Index(locs),
+        _ => Sitemap::UrlSet(locs),
+    })
+}
+
+async fn fetch_text(client: &Client, url: &Url) -> Result<String, String> {
+    let response = client
+        .get(url.clone())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(response.status().to_string());
+    }
+
+    let bytes = response.bytes().await.map_err(|e| e.to_string())?;
+
+    // Gzipped sitemaps are served as-is, usually as application/gzip or octet-stream
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut text = String::new();
+        GzDecoder::new(&bytes[..])
+            .read_to_string(&mut text)
+            .map_err(|e| e.to_string())?;
+        Ok(text)
+    } else {
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+/// Follow the sitemaps of the site at `base`, as announced in its robots.txt or otherwise at the
+/// default `/sitemap.xml` location, including any sitemap indexes, and return every listed page on
+/// the site (as far as the `scope` is concerned) together with the sitemap it was found in.
+pub async fn discover(
+    client: &Client,
+    base: &Url,
+    scope: &Scope,
+    announced: &[String],
+    events: &Sender<Event>,
+) -> Vec<(Url, Url)> {
+    let info = |message: String| events.send(Event::Info(message));
+
+    let mut queue: Vec<Url> = announced
+        .iter()
+        .filter_map(|loc| base.join(loc).ok())
+        .collect();
+    if queue.is_empty() {
+        queue.push(base.join("/sitemap.xml").unwrap());
+    }
+
+    let mut visited = HashSet::new();
+    let mut pages = Vec::new();
+
+    while let Some(sitemap_url) = queue.pop() {
+        if !visited.insert(sitemap_url.clone()) {
+            continue;
+        }
+
+        let text = match fetch_text(client, &sitemap_url).await {
+            Ok(text) => text,
+            Err(error) => {
+                let _ = info(format!("sitemap: {sitemap_url} {error}")).await;
+                continue;
+            }
+        };
+
+        match parse(&text) {
+            Ok(Sitemap::Index(locs)) => {
+                queue.extend(locs.iter().filter_map(|loc| sitemap_url.join(loc).ok()));
+            }
+            Ok(Sitemap::UrlSet(locs)) => {
+                let found = format!("sitemap: {sitemap_url} {} URL's found", locs.len());
+                let _ = info(found).await;
+                pages.extend(
+                    locs.iter()
+                        .filter_map(|loc| sitemap_url.join(loc).ok())
+                        .filter(|url| scope.is_internal(url))
+                        .map(|url| (url, sitemap_url.clone())),
+                );
+            }
+            Err(error) => {
+                let _ = info(format!("sitemap: {sitemap_url} {error}")).await;
+            }
+        }
+    }
+
+    pages
+}
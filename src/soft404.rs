@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use regex::Regex;
+use reqwest::{Client, Url};
+
+use crate::{
+    check::{Check, Finding, Response},
+    LinkKind,
+};
+
+/// The number of words hashed together, so that the order of the words counts as well.
+const SHINGLE_SIZE: usize = 3;
+
+/// The number of bits two hashes may differ in for their texts to count as near-identical.
+const MAX_DISTANCE: u32 = 3;
+
+/// A SimHash of the words in `text`, see <https://en.wikipedia.org/wiki/SimHash>. Unlike a regular
+/// hash, the hashes of texts that are nearly the same differ in only a few bits.
+fn simhash(text: &str) -> u64 {
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    let mut weights = [0i32; 64];
+    for shingle in words.windows(SHINGLE_SIZE.min(words.len()).max(1)) {
+        let mut hasher = DefaultHasher::new();
+        shingle.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            match hash >> bit & 1 {
+                1 => *weight += 1,
+                _ => *weight -= 1,
+            }
+        }
+    }
+
+    weights
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight > 0)
+        .fold(0, |hash, (bit, _)| hash | 1 << bit)
+}
+
+/// The error page of every host that serves one with a successful status, by origin. Hosts that
+/// respond to a URL that does not exist with a proper error status have `None`.
+pub type Fingerprints = Arc<Mutex<HashMap<String, Option<u64>>>>;
+
+/// Request a URL that cannot exist on the host of `url`, and fingerprint the response if the host
+/// pretends it does. Hosts that redirect it (to the home page, usually) are left alone, as the
+/// page they redirect to is a real page.
+pub async fn fingerprint(client: &Client, url: &Url) -> Option<u64> {
+    let probe = url
+        .join(&format!("/tgcheck-soft-404-{:016x}", fastrand::u64(..)))
+        .ok()?;
+    let response = client.get(probe.clone()).send().await.ok()?;
+    if !response.status().is_success() || response.url() != &probe {
+        return None;
+    }
+
+    Some(simhash(&response.text().await.ok()?))
+}
+
+/// Fails pages that are served with a successful status, but are really an error page: their
+/// title matches one of the patterns, or they look just like the error page of their host.
+#[derive(Debug)]
+pub struct SoftNotFound {
+    pub fingerprints: Fingerprints,
+    pub titles: Vec<Regex>,
+}
+
+impl Check for SoftNotFound {
+    fn name(&self) -> &str {
+        "soft-404"
+    }
+
+    fn check(&self, response: &Response) -> Vec<Finding> {
+        let Some(body) = response.body else {
+            return Vec::new();
+        };
+        if response.kind != LinkKind::Page || !response.status.is_success() {
+            return Vec::new();
+        }
+
+        if let Some(title) = response.title {
+            if let Some(pattern) = self.titles.iter().find(|pattern| pattern.is_match(title)) {
+                let message = format!("the title \"{title}\" matches {pattern}");
+                return vec![Finding::error(message)];
+            }
+        }
+
+        let origin = response.url.origin().ascii_serialization();
+        let fingerprint = self.fingerprints.lock().unwrap().get(&origin).copied();
+        match fingerprint.flatten() {
+            Some(fingerprint) if (fingerprint ^ simhash(body)).count_ones() <= MAX_DISTANCE => {
+                vec![Finding::error(
+                    "looks like the error page for a URL that does not exist",
+                )]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
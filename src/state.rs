@@ -0,0 +1,189 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::ResponseResult;
+
+/// (De)serialize a `StatusCode` as its number.
+pub mod status {
+    use reqwest::StatusCode;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(status: &StatusCode, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(status.as_u16())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<StatusCode, D::Error> {
+        StatusCode::from_u16(u16::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+
+    pub mod option {
+        use reqwest::StatusCode;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            status: &Option<StatusCode>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match status {
+                Some(status) => super::serialize(status, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<StatusCode>, D::Error> {
+            #[derive(Deserialize)]
+            struct Status(#[serde(with = "super")] StatusCode);
+
+            Ok(Option::<Status>::deserialize(deserializer)?.map(|Status(status)| status))
+        }
+    }
+}
+
+/// An append-only log of every result, one JSON object per line, so an interrupted crawl can pick
+/// up where it left off. The links found on a page are part of its result, which makes the log
+/// enough to rebuild the queue as well.
+pub struct StateFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl StateFile {
+    /// Open the state file at `path`, and read the results of the previous run, if any. A line that
+    /// was cut off halfway when the previous run died is dropped.
+    pub fn open(path: &Path) -> io::Result<(StateFile, Vec<ResponseResult>)> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut reader = BufReader::new(&file);
+        let mut results = Vec::new();
+        let mut line = String::new();
+        let mut complete = 0;
+
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            match serde_json::from_str(&line) {
+                Ok(result) => results.push(result),
+                Err(_) => break,
+            }
+            complete += read as u64;
+        }
+
+        // New results should not end up behind a broken line
+        file.set_len(complete)?;
+
+        Ok((
+            StateFile {
+                path: path.to_owned(),
+                file,
+            },
+            results,
+        ))
+    }
+
+    pub fn record(&mut self, result: &ResponseResult) -> io::Result<()> {
+        let mut line = serde_json::to_vec(result)?;
+        line.push(b'\n');
+        self.file.write_all(&line)
+    }
+
+    /// The crawl is complete, so there is nothing left to resume.
+    pub fn remove(self) -> io::Result<()> {
+        drop(self.file);
+        fs::remove_file(self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("tgcheck-{}-{name}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn result(url: &str) -> ResponseResult {
+        ResponseResult {
+            url: url.to_owned(),
+            status: Some(StatusCode::NOT_FOUND),
+            ..Default::default()
+        }
+    }
+
+    fn urls(results: &[ResponseResult]) -> Vec<&str> {
+        results.iter().map(|result| result.url.as_str()).collect()
+    }
+
+    #[test]
+    fn record_and_resume() {
+        let path = path("resume");
+        let (mut state_file, results) = StateFile::open(&path).unwrap();
+        assert!(results.is_empty());
+        state_file.record(&result("https://example.com/a")).unwrap();
+        state_file.record(&result("https://example.com/b")).unwrap();
+        drop(state_file);
+
+        let (state_file, results) = StateFile::open(&path).unwrap();
+        assert_eq!(
+            urls(&results),
+            ["https://example.com/a", "https://example.com/b"]
+        );
+        assert_eq!(results[1].status, Some(StatusCode::NOT_FOUND));
+
+        state_file.remove().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn drop_half_written_line() {
+        let path = path("half");
+        let (mut state_file, _) = StateFile::open(&path).unwrap();
+        state_file.record(&result("https://example.com/a")).unwrap();
+        state_file
+            .file
+            .write_all(br#"{"from":"","url":"https://exa"#)
+            .unwrap();
+        drop(state_file);
+
+        let (mut state_file, results) = StateFile::open(&path).unwrap();
+        assert_eq!(urls(&results), ["https://example.com/a"]);
+        // The broken line is cut off, so the next result starts on a line of its own
+        state_file.record(&result("https://example.com/c")).unwrap();
+        drop(state_file);
+
+        let (state_file, results) = StateFile::open(&path).unwrap();
+        assert_eq!(
+            urls(&results),
+            ["https://example.com/a", "https://example.com/c"]
+        );
+        state_file.remove().unwrap();
+    }
+
+    #[test]
+    fn stop_at_broken_line() {
+        let path = path("broken");
+        let (mut state_file, _) = StateFile::open(&path).unwrap();
+        state_file.record(&result("https://example.com/a")).unwrap();
+        state_file.file.write_all(b"not json\n").unwrap();
+        state_file.record(&result("https://example.com/b")).unwrap();
+        drop(state_file);
+
+        let (state_file, results) = StateFile::open(&path).unwrap();
+        assert_eq!(urls(&results), ["https://example.com/a"]);
+        state_file.remove().unwrap();
+    }
+}